@@ -25,7 +25,8 @@ pub async fn run(config: Config) -> std::io::Result<()> {
             let aggregator = metric_aggregator_factory(
                 hub.clone(),
                 server_config,
-                config.authentication.clone(),
+                server_config.auth_methods(&config.authentication),
+                config.reconnect.clone(),
                 index as u8 + 1,
             );
             Actor::start_in_arbiter(&Arbiter::new().handle(), |_| aggregator);
@@ -1,7 +1,8 @@
 use serde_derive::Deserialize;
 use std::error::Error;
 use std::fs::read_to_string;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use toml;
 
 #[derive(Debug, Deserialize)]
@@ -10,6 +11,8 @@ pub struct Config {
     pub port: u32,
     #[serde(default)]
     pub authentication: AuthMethod,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
     pub servers: Option<Vec<ServerConfig>>,
 }
 
@@ -19,12 +22,57 @@ impl Config {
     }
 }
 
+/// Delays in seconds between attempts to reach a server that can't be connected to
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    pub initial_delay: f64,
+    pub max_delay: f64,
+    /// Share of the delay that is randomly taken off, so that servers don't retry in lockstep
+    pub jitter: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1.0,
+            max_delay: 60.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// The delay doubles with every failed attempt until it reaches the maximum
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(32) as i32;
+        let delay = (self.initial_delay * 2f64.powi(exponent)).min(self.max_delay);
+        let jitter = delay * self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+        Duration::from_secs_f64((delay - jitter).max(0.0))
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "method")]
 #[serde(rename_all = "snake_case")]
 pub enum AuthMethod {
     SshAgent,
     PubKey(PubKeyConfig),
+    Certificate(CertificateConfig),
+    Password(SecretConfig),
+    KeyboardInteractive(SecretConfig),
+}
+
+impl AuthMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthMethod::SshAgent => "ssh_agent",
+            AuthMethod::PubKey(_) => "pub_key",
+            AuthMethod::Certificate(_) => "certificate",
+            AuthMethod::Password(_) => "password",
+            AuthMethod::KeyboardInteractive(_) => "keyboard_interactive",
+        }
+    }
 }
 
 impl Default for AuthMethod {
@@ -54,13 +102,334 @@ impl PubKeyConfig {
     }
 }
 
+/// A private key together with the OpenSSH certificate that a CA issued for it
+#[derive(Debug, Deserialize, Clone)]
+pub struct CertificateConfig {
+    pub private_key: String,
+    /// Defaults to the private key path followed by -cert.pub, like ssh-keygen names it
+    pub certificate: Option<String>,
+    pub passphrase: Option<String>,
+}
+
+impl CertificateConfig {
+    pub fn certificate_path(&self) -> PathBuf {
+        match &self.certificate {
+            Some(certificate) => PathBuf::from(certificate),
+            None => PathBuf::from(format!("{}-cert.pub", self.private_key)),
+        }
+    }
+
+    pub fn private_key_path(&self) -> &Path {
+        Path::new(&self.private_key)
+    }
+
+    pub fn passphrase(&self) -> Option<&str> {
+        self.passphrase.as_deref()
+    }
+}
+
+/// The secret is taken from the config, an environment variable or a file, in that order
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecretConfig {
+    pub password: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+}
+
+impl SecretConfig {
+    pub fn secret(&self) -> Result<String, Box<dyn Error>> {
+        if let Some(password) = &self.password {
+            return Ok(password.clone());
+        }
+        if let Some(name) = &self.password_env {
+            return std::env::var(name)
+                .map_err(|e| format!("Can't read password from ${}: {}", name, e).into());
+        }
+        if let Some(path) = &self.password_file {
+            let password = read_to_string(path)
+                .map_err(|e| format!("Can't read password from {}: {}", path, e))?;
+            return Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string());
+        }
+        Err("One of password, password_env or password_file has to be set".into())
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
     pub hostname: String,
+    /// Not needed by the local transport
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
+    pub transport: TransportConfig,
+    /// Methods that are tried in order, the global authentication is used when empty
+    #[serde(default)]
+    pub authentication: Vec<AuthMethod>,
     pub disk: String,
     pub filesystem: String,
     pub network_interface: String,
+    #[serde(default)]
+    pub systemd: Option<SystemdConfig>,
+    #[serde(default)]
+    pub cgroups: Option<CgroupConfig>,
+    #[serde(default)]
+    pub custom_metrics: Vec<CustomMetricConfig>,
+    #[serde(default)]
+    pub log_patterns: Vec<LogPatternConfig>,
+    #[serde(default)]
+    pub watch_processes: Vec<WatchProcessConfig>,
+    #[serde(default)]
+    pub nginx: Option<NginxConfig>,
+    #[serde(default)]
+    pub redis: Option<RedisConfig>,
+    #[serde(default)]
+    pub postgres: Option<PostgresConfig>,
+    #[serde(default)]
+    pub haproxy: Option<HaproxyConfig>,
+    #[serde(default)]
+    pub updates: Option<UpdatesConfig>,
+    /// Reads /proc and /sys files over SFTP instead of running commands for them, SSH only
+    #[serde(default)]
+    pub sftp: bool,
+    /// Keeps the queries running in a loop on the server instead of starting them every second
+    #[serde(default)]
+    pub streaming: Option<StreamingConfig>,
+}
+
+impl ServerConfig {
+    pub fn auth_methods(&self, global: &AuthMethod) -> Vec<AuthMethod> {
+        if self.authentication.is_empty() {
+            vec![global.clone()]
+        } else {
+            self.authentication.clone()
+        }
+    }
+}
+
+/// How queries reach the server
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+pub enum TransportConfig {
+    #[default]
+    Ssh,
+    /// Runs queries with the ssh binary instead of the built-in client
+    Openssh(OpensshConfig),
+    /// Runs queries on the machine Hearth runs on
+    Local(LocalConfig),
+    /// Runs queries through a local command that forwards them, e.g. into a container
+    Exec(ExecConfig),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct OpensshConfig {
+    /// Taken from ssh_config when missing
+    pub port: Option<u16>,
+    /// Extra options passed with -o, e.g. "ProxyJump=bastion"
+    pub options: Vec<String>,
+    /// How long the master connection outlives the last query, in ssh_config syntax
+    pub control_persist: String,
+    /// Where the master connection sockets are kept, a directory in /tmp by default
+    pub control_dir: Option<String>,
+}
+
+impl Default for OpensshConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            options: vec![],
+            control_persist: "60".to_string(),
+            control_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LocalConfig {
+    /// Directory the host's /proc, /sys and /etc are mounted below, e.g. /host in a container
+    pub root: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExecConfig {
+    /// Template like `docker exec {name} sh -c {cmd}`, {name} is replaced by the hostname and
+    /// {cmd} by the quoted query
+    pub command: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SystemdConfig {
+    #[serde(default)]
+    pub critical_units: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CgroupConfig {
+    /// Paths relative to /sys/fs/cgroup, docker and podman containers are used when empty
+    #[serde(default)]
+    pub slices: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomMetricConfig {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub parser: CustomParser,
+    #[serde(default)]
+    pub unit: String,
+    /// Counters are ever-growing values that are reported as a rate per second
+    #[serde(default)]
+    pub counter: bool,
+    #[serde(default)]
+    pub aggregation: Aggregation,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomParser {
+    /// The first number in the output
+    #[default]
+    Number,
+    /// One `key=value` pair per line
+    KeyValue,
+    /// A JSON object, nested keys are joined with dots
+    Json,
+}
+
+/// Determines how values are combined across nodes and over time
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    #[default]
+    Sum,
+    Avg,
+    Max,
+    Min,
+}
+
+/// Lines matching the pattern are counted in the file, or in the journal when no file is given
+#[derive(Debug, Deserialize, Clone)]
+pub struct LogPatternConfig {
+    pub name: String,
+    pub pattern: String,
+    pub file: Option<String>,
+    /// Restricts journal lines to a single systemd unit
+    pub unit: Option<String>,
+}
+
+/// Processes are matched with pgrep, by default against their name
+#[derive(Debug, Deserialize, Clone)]
+pub struct WatchProcessConfig {
+    pub name: String,
+    /// Regular expression, the name is used when it is missing
+    pub pattern: Option<String>,
+    /// Matches the pattern against the full command line instead of the process name
+    #[serde(default)]
+    pub full_command: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct NginxConfig {
+    /// Location of the stub_status page as seen from the server itself
+    pub status_url: String,
+}
+
+impl Default for NginxConfig {
+    fn default() -> Self {
+        Self {
+            status_url: "http://127.0.0.1/nginx_status".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RedisConfig {
+    pub host: String,
+    pub port: u16,
+    /// Shows up in the command line of the shell running the query on the server
+    pub password: Option<String>,
+    /// File on the server holding the password, it is only read there
+    pub password_file: Option<String>,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            password: None,
+            password_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PostgresConfig {
+    pub database: String,
+    pub user: Option<String>,
+    /// Runs psql as this user through sudo, e.g. postgres for peer authentication
+    pub sudo_user: Option<String>,
+}
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        Self {
+            database: "postgres".to_string(),
+            user: None,
+            sudo_user: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct HaproxyConfig {
+    /// Path of the stats socket, it is read with socat
+    pub socket: String,
+}
+
+impl Default for HaproxyConfig {
+    fn default() -> Self {
+        Self {
+            socket: "/run/haproxy/admin.sock".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct UpdatesConfig {
+    /// Seconds between checks, package managers are too slow to be asked every second
+    pub interval: u64,
+    /// Seconds after which a check is given up on, it runs apart from the other queries
+    pub timeout: u64,
+}
+
+impl Default for UpdatesConfig {
+    fn default() -> Self {
+        Self {
+            interval: 900,
+            timeout: 120,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct StreamingConfig {
+    /// Seconds without a sample after which the loop is started again
+    pub stall_timeout: f64,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self { stall_timeout: 5.0 }
+    }
 }
 
 pub fn load_config() -> Result<Config, Box<dyn Error>> {
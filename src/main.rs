@@ -1,7 +1,8 @@
 mod app;
 mod config;
 mod metrics;
-mod ssh;
+mod sysinfo;
+mod transport;
 mod ws;
 
 #[actix_rt::main]
@@ -1,15 +1,42 @@
 use super::hub::MetricHub;
-use crate::config::{AuthMethod, ServerConfig};
+use crate::config::{AuthMethod, ReconnectConfig, ServerConfig, StreamingConfig, TransportConfig};
 use crate::metrics::{
-    cpu::CpuMetrics, disk::DiskMetrics, la::LaMetrics, network::NetMetrics, ram::RamMetrics,
-    space::SpaceMetrics, MetricPlugin, Metrics,
+    cgroup::CgroupMetrics,
+    clock::ClockMetrics,
+    collection::CollectionMetrics,
+    cpu::CpuMetrics,
+    custom::CustomMetrics,
+    disk::DiskMetrics,
+    event::{EventKind, NodeEvent},
+    haproxy::HaproxyMetrics,
+    kernel::KernelMetrics,
+    la::LaMetrics,
+    limits::LimitsMetrics,
+    log::LogMetrics,
+    network::NetMetrics,
+    nginx::NginxMetrics,
+    ports::PortMetrics,
+    postgres::PostgresMetrics,
+    process::ProcessMetrics,
+    raid::RaidMetrics,
+    ram::RamMetrics,
+    redis::RedisMetrics,
+    space::SpaceMetrics,
+    systemd::SystemdMetrics,
+    updates::UpdateMetrics,
+    MetricPlugin, Metrics,
+};
+use crate::sysinfo::SystemInfo;
+use crate::transport::{
+    transport_factory, BackgroundQuery, ConnectionError, ConnectionState, QueryError, QueryResult,
+    Sample, SampleStream, Transport,
 };
-use crate::ssh::SshClient;
 use actix::prelude::*;
-use log::{error, info};
+use log::{debug, info, warn};
 use serde_derive::Serialize;
+use std::collections::BTreeMap;
 use std::ops::Add;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Default, Clone, Serialize, Message)]
 #[rtype(result = "()")]
@@ -34,6 +61,12 @@ pub struct NodeSpecs {
     hostname: String,
     cpus: u16,
     ip: String,
+    /// Unix timestamp of the last successful collection, 0 if there was none yet
+    last_success: u64,
+    system: SystemInfo,
+    connection: ConnectionState,
+    /// Plugins whose query failed the last time it was run, with the reason
+    failing_queries: Vec<String>,
 }
 
 impl NodeSpecs {
@@ -43,6 +76,10 @@ impl NodeSpecs {
             hostname,
             cpus,
             ip,
+            last_success: 0,
+            system: SystemInfo::default(),
+            connection: ConnectionState::default(),
+            failing_queries: vec![],
         }
     }
 
@@ -57,6 +94,25 @@ impl NodeSpecs {
     pub fn update_cpus(&mut self, cpus: u16) {
         self.cpus = cpus;
     }
+
+    pub fn update_last_success(&mut self, last_success: Option<SystemTime>) {
+        self.last_success = last_success
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+    }
+
+    pub fn update_system_info(&mut self, system: SystemInfo) {
+        self.system = system;
+    }
+
+    pub fn update_connection(&mut self, connection: ConnectionState) {
+        self.connection = connection;
+    }
+
+    pub fn update_failing_queries(&mut self, failing_queries: Vec<String>) {
+        self.failing_queries = failing_queries;
+    }
 }
 
 /// Node Metrics are time-series data that changes often
@@ -68,13 +124,29 @@ pub struct NodeMetrics {
     hostname: String,
     online: bool,
     uptime_seconds: u64,
+    collection: CollectionMetrics,
 
+    clock: ClockMetrics,
     cpu: CpuMetrics,
     disk: DiskMetrics,
+    kernel: KernelMetrics,
     la: LaMetrics,
+    limits: LimitsMetrics,
     net: NetMetrics,
+    ports: PortMetrics,
+    raid: RaidMetrics,
     ram: RamMetrics,
     space: SpaceMetrics,
+    systemd: Option<SystemdMetrics>,
+    cgroups: Option<CgroupMetrics>,
+    custom: BTreeMap<String, CustomMetrics>,
+    logs: Option<LogMetrics>,
+    processes: Option<ProcessMetrics>,
+    nginx: Option<NginxMetrics>,
+    redis: Option<RedisMetrics>,
+    postgres: Option<PostgresMetrics>,
+    haproxy: Option<HaproxyMetrics>,
+    updates: Option<UpdateMetrics>,
 }
 
 impl Add for NodeMetrics {
@@ -85,17 +157,55 @@ impl Add for NodeMetrics {
             hostname: "".to_string(),
             online: self.online,
             uptime_seconds: self.uptime_seconds,
+            collection: self.collection + other.collection,
 
+            clock: self.clock + other.clock,
             cpu: self.cpu + other.cpu,
             disk: self.disk + other.disk,
+            kernel: self.kernel + other.kernel,
             la: self.la + other.la,
+            limits: self.limits + other.limits,
             net: self.net + other.net,
+            ports: self.ports + other.ports,
+            raid: self.raid + other.raid,
             ram: self.ram + other.ram,
             space: self.space + other.space,
+            systemd: add_optional(self.systemd, other.systemd),
+            cgroups: add_optional(self.cgroups, other.cgroups),
+            custom: add_custom(self.custom, other.custom),
+            logs: add_optional(self.logs, other.logs),
+            processes: add_optional(self.processes, other.processes),
+            nginx: add_optional(self.nginx, other.nginx),
+            redis: add_optional(self.redis, other.redis),
+            postgres: add_optional(self.postgres, other.postgres),
+            haproxy: add_optional(self.haproxy, other.haproxy),
+            updates: add_optional(self.updates, other.updates),
         }
     }
 }
 
+/// Metrics of optional plugins are only present on the nodes where the plugin is enabled
+fn add_optional<T: Add<Output = T>>(this: Option<T>, that: Option<T>) -> Option<T> {
+    match (this, that) {
+        (Some(this), Some(that)) => Some(this + that),
+        (this, that) => this.or(that),
+    }
+}
+
+fn add_custom(
+    mut this: BTreeMap<String, CustomMetrics>,
+    that: BTreeMap<String, CustomMetrics>,
+) -> BTreeMap<String, CustomMetrics> {
+    for (name, metrics) in that {
+        let combined = match this.remove(&name) {
+            Some(existing) => existing + metrics,
+            None => metrics,
+        };
+        this.insert(name, combined);
+    }
+    this
+}
+
 impl NodeMetrics {
     pub fn hostname(&self) -> &str {
         &self.hostname
@@ -105,13 +215,21 @@ impl NodeMetrics {
         let mut cluster = NodeMetrics::default();
         let node_count = nodes.len();
 
-        for node in nodes {
+        for mut node in nodes {
+            node.raid = node.raid.on_node(&node.hostname);
             cluster = cluster + node;
         }
 
         if node_count > 0 {
             cluster.cpu = cluster.cpu.divide(node_count as f32);
+            cluster.collection = cluster.collection.divide(node_count as f64);
         }
+        cluster.cgroups = cluster.cgroups.map(|m| m.into_single_sample());
+        cluster.custom = cluster
+            .custom
+            .into_iter()
+            .map(|(name, metrics)| (name, metrics.aggregate_nodes()))
+            .collect();
 
         cluster.hostname = "Cluster".to_string();
         cluster
@@ -132,11 +250,33 @@ impl NodeMetrics {
 
         if measurement_count > 0 {
             average.cpu = average.cpu.divide(measurement_count as f32);
+            average.collection = average.collection.divide(measurement_count as f64);
+            average.clock = average.clock.divide(measurement_count as f64);
             average.disk = average.disk.divide(measurement_count as f64);
+            average.kernel = average.kernel.divide(measurement_count as f64);
             average.la = average.la.divide(measurement_count as f64);
+            average.limits = average.limits.divide(measurement_count as f64);
             average.net = average.net.divide(measurement_count as f64);
+            average.ports = average.ports.divide(measurement_count as f64);
+            average.raid = average.raid.divide(measurement_count as f64);
             average.ram = average.ram.divide(measurement_count as u64);
             average.space = average.space.divide(measurement_count as u64);
+            average.systemd = average.systemd.map(|m| m.divide(measurement_count as f64));
+            average.cgroups = average.cgroups.map(|m| m.average());
+            average.logs = average.logs.map(|m| m.divide(measurement_count as f64));
+            average.processes = average
+                .processes
+                .map(|m| m.divide(measurement_count as f64));
+            average.nginx = average.nginx.map(|m| m.divide(measurement_count as f64));
+            average.redis = average.redis.map(|m| m.divide(measurement_count as f64));
+            average.postgres = average.postgres.map(|m| m.divide(measurement_count as f64));
+            average.haproxy = average.haproxy.map(|m| m.divide(measurement_count as f64));
+            average.updates = average.updates.map(|m| m.divide(measurement_count as f64));
+            average.custom = average
+                .custom
+                .into_iter()
+                .map(|(name, metrics)| (name, metrics.average()))
+                .collect();
         }
 
         average
@@ -145,12 +285,29 @@ impl NodeMetrics {
     pub fn set(&mut self, metrics: Metrics) {
         use Metrics::*;
         match metrics {
+            Cgroup(m) => self.cgroups = Some(m),
+            Clock(m) => self.clock = m,
             Cpu(m) => self.cpu = m,
+            Custom(m) => {
+                self.custom.insert(m.name().to_string(), m);
+            }
             Disk(m) => self.disk = m,
+            Haproxy(m) => self.haproxy = Some(m),
+            Kernel(m) => self.kernel = m,
             La(m) => self.la = m,
+            Limits(m) => self.limits = m,
+            Log(m) => self.logs = Some(m),
             Net(m) => self.net = m,
+            Nginx(m) => self.nginx = Some(m),
+            Ports(m) => self.ports = m,
+            Postgres(m) => self.postgres = Some(m),
+            Process(m) => self.processes = Some(m),
+            Raid(m) => self.raid = m,
             Ram(m) => self.ram = m,
+            Redis(m) => self.redis = Some(m),
             Space(m) => self.space = m,
+            Systemd(m) => self.systemd = Some(m),
+            Updates(m) => self.updates = Some(m),
         }
     }
 }
@@ -158,21 +315,19 @@ impl NodeMetrics {
 pub fn metric_aggregator_factory(
     hub: Addr<MetricHub>,
     server_config: &ServerConfig,
-    auth_method: AuthMethod,
+    auth_methods: Vec<AuthMethod>,
+    reconnect: ReconnectConfig,
     index: u8,
 ) -> MetricAggregator {
-    let ssh = SshClient::new(
-        server_config.username.clone(),
-        auth_method,
-        server_config.hostname.clone(),
-        22,
-    );
-    let plugins = super::metric_plugin_factory(
-        &server_config.disk,
-        &server_config.filesystem,
-        &server_config.network_interface,
+    let transport = transport_factory(server_config, auth_methods, reconnect);
+    let plugins = super::metric_plugin_factory(server_config);
+    let aggregator = MetricProvider::new(
+        transport,
+        plugins,
+        server_config.streaming.clone(),
+        // Local files are always cheaper to read than starting a shell for them
+        server_config.sftp || matches!(server_config.transport, TransportConfig::Local(_)),
     );
-    let aggregator = MetricProvider::new(ssh, plugins);
 
     MetricAggregator::new(hub, aggregator, index)
 }
@@ -203,19 +358,27 @@ impl MetricAggregator {
         self.hub.do_send(metrics);
     }
 
-    fn send_specs(&mut self, ctx: &mut actix::Context<Self>) {
-        // TODO: implement proper initialization
-        let ping = self.provider.ssh.run("|");
-        let specs = NodeSpecs::new(
+    fn specs(&self) -> NodeSpecs {
+        let mut specs = NodeSpecs::new(
             self.index,
-            self.provider.ssh.get_hostname().to_string(),
-            self.provider.ssh.get_cpus() as u16,
-            self.provider.ssh.get_ip().to_string(),
+            self.provider.transport.get_hostname().to_string(),
+            self.provider.transport.get_cpus() as u16,
+            self.provider.transport.get_ip().to_string(),
         );
-        self.hub.do_send(specs);
+        specs.update_last_success(self.provider.last_success);
+        specs.update_system_info(self.provider.transport.get_system_info().clone());
+        specs.update_connection(self.provider.transport.get_state().clone());
+        specs.update_failing_queries(self.provider.failing_queries());
+        specs
+    }
+
+    fn send_specs(&mut self, ctx: &mut actix::Context<Self>) {
+        // Connecting gathers the system information that is part of the specs
+        let ping = self.provider.transport.run("true");
+        self.hub.do_send(self.specs());
 
         if ping.is_err() {
-            let delay = Duration::new(1, 0);
+            let delay = self.provider.transport.get_state().retry_delay();
             ctx.run_later(delay, move |aggregator, ctx| {
                 aggregator.send_specs(ctx);
             });
@@ -226,7 +389,7 @@ impl MetricAggregator {
         let delay = Duration::new(60, 0);
 
         ctx.run_later(delay, move |aggregator, ctx| {
-            aggregator.provider.ssh.update_uptime();
+            aggregator.provider.transport.update_uptime();
             aggregator.update_uptime(ctx);
         });
     }
@@ -236,13 +399,25 @@ impl Actor for MetricAggregator {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        info!("[{}] Aggregator started", self.provider.ssh.get_hostname());
+        info!(
+            "[{}] Aggregator started",
+            self.provider.transport.get_hostname()
+        );
         self.send_specs(ctx);
         let interval = Duration::new(0, 1_000_000_000);
 
         ctx.run_interval(interval, move |aggregator, ctx| {
             let metrics = aggregator.aggregate(ctx);
             aggregator.send_metrics(metrics, ctx);
+            // Keeps the time of the last successful collection up to date
+            aggregator.hub.do_send(aggregator.specs());
+
+            let hostname = aggregator.provider.transport.get_hostname().to_string();
+            for kind in aggregator.provider.take_events() {
+                aggregator
+                    .hub
+                    .do_send(NodeEvent::new(hostname.clone(), kind));
+            }
         });
 
         self.update_uptime(ctx);
@@ -253,65 +428,425 @@ impl Actor for MetricAggregator {
 ///
 /// Retrieves data from a server using the available Metric Plugins
 pub struct MetricProvider {
-    ssh: SshClient,
+    transport: Box<dyn Transport>,
     metric_plugins: Vec<Box<dyn MetricPlugin>>,
+    plugin_states: Vec<PluginState>,
+    streaming: Option<StreamingConfig>,
+    /// Which plugins are part of the stream, the others are queried one batch at a time
+    streamed: Vec<bool>,
+    stream: Option<SampleStream>,
+    /// Slow queries that are running apart from the batches, by plugin
+    background: Vec<Option<BackgroundQuery>>,
+    /// Whether files are read directly for plugins that only need their contents
+    sftp: bool,
+    last_success: Option<SystemTime>,
+    boot_id: String,
+    uptime_seconds: u64,
+    events: Vec<EventKind>,
 }
 
 impl MetricProvider {
-    pub fn new(ssh: SshClient, metric_providers: Vec<Box<dyn MetricPlugin>>) -> Self {
+    pub fn new(
+        transport: Box<dyn Transport>,
+        metric_providers: Vec<Box<dyn MetricPlugin>>,
+        streaming: Option<StreamingConfig>,
+        sftp: bool,
+    ) -> Self {
         Self {
-            ssh,
+            transport,
+            plugin_states: metric_providers
+                .iter()
+                .map(|plugin| PluginState::new(plugin.empty_metrics().name()))
+                .collect(),
+            streamed: metric_providers
+                .iter()
+                .map(|plugin| {
+                    streaming.is_some()
+                        && plugin.streamable()
+                        && plugin.interval() <= STREAM_INTERVAL
+                        && plugin.timeout().is_none()
+                })
+                .collect(),
+            streaming,
+            stream: None,
+            background: metric_providers.iter().map(|_| None).collect(),
+            sftp,
             metric_plugins: metric_providers,
+            last_success: None,
+            boot_id: "".to_string(),
+            uptime_seconds: 0,
+            events: vec![],
         }
     }
 
+    fn failing_queries(&self) -> Vec<String> {
+        self.plugin_states
+            .iter()
+            .filter_map(|state| {
+                state
+                    .error
+                    .as_ref()
+                    .map(|error| format!("{}: {}", state.name, error))
+            })
+            .collect()
+    }
+
+    fn take_events(&mut self) -> Vec<EventKind> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Notices reboots by a changed boot id or by an uptime that went backwards
+    fn detect_reboot(&mut self) -> bool {
+        let boot_id = self.transport.get_system_info().boot_id.clone();
+        let uptime_seconds = self.transport.get_uptime();
+
+        let rebooted = (!self.boot_id.is_empty() && !boot_id.is_empty() && boot_id != self.boot_id)
+            || (uptime_seconds > 0 && uptime_seconds < self.uptime_seconds);
+
+        if !boot_id.is_empty() {
+            self.boot_id = boot_id;
+        }
+        if uptime_seconds > 0 {
+            self.uptime_seconds = uptime_seconds;
+        }
+        rebooted
+    }
+
     fn get_metrics(&mut self) -> NodeMetrics {
+        self.transport.reset_timings();
+        let started = Instant::now();
         let mut aggregate = self.batch_fetch();
-        aggregate.hostname = self.ssh.get_hostname().to_string();
-        aggregate.uptime_seconds = self.ssh.get_uptime();
+        if aggregate.online {
+            aggregate.collection =
+                CollectionMetrics::new(self.transport.get_timings(), started.elapsed());
+            self.last_success = Some(SystemTime::now());
+        }
+        aggregate.hostname = self.transport.get_hostname().to_string();
+        aggregate.uptime_seconds = self.transport.get_uptime();
         aggregate
     }
 
     fn batch_fetch(&mut self) -> NodeMetrics {
-        let merged_command = self
+        let mut due: Vec<bool> = self
             .metric_plugins
             .iter()
-            .fold("".to_string(), |accum, provider| {
-                if accum == "" {
-                    return provider.get_query().to_string();
+            .zip(self.plugin_states.iter())
+            .map(|(plugin, state)| plugin.timeout().is_none() && state.is_due(plugin.interval()))
+            .collect();
+        let mut results: Vec<Option<(QueryResult, SystemTime)>> = vec![None; due.len()];
+
+        if self.streamed.contains(&true) {
+            let sample = match self.stream_sample() {
+                Ok(sample) => sample,
+                Err(e) => {
+                    debug!(
+                        "[{}]: Streaming failed: {}",
+                        self.transport.get_hostname(),
+                        e
+                    );
+                    return self.build_empty_metrics();
                 }
-                format!("{} && printf '######' && {}", accum, provider.get_query())
-            });
+            };
+
+            // Streamed plugins are processed whenever a new sample has arrived
+            let received = sample.as_ref().map(|sample| sample.received);
+            let mut outputs = sample
+                .map(|sample| sample.results)
+                .unwrap_or_default()
+                .into_iter();
+            for (index, _) in self.streamed.iter().enumerate().filter(|(_, &s)| s) {
+                due[index] = received.is_some();
+                results[index] = outputs.next().zip(received);
+            }
+        }
+
+        if self.sftp && self.transport.can_read_files() {
+            if let Err(e) = self.read_files(&due, &mut results) {
+                debug!(
+                    "[{}]: Reading files failed: {}",
+                    self.transport.get_hostname(),
+                    e
+                );
+                // Servers without SFTP get the queries instead
+                if self.transport.can_read_files() && self.stream.is_none() {
+                    return self.build_empty_metrics();
+                }
+            }
+        }
 
-        match self.ssh.run(&merged_command) {
-            Ok(raw_data) => self.process_raw_data(&raw_data),
-            Err(e) => {
-                error!("[{}]: SSH FAILED: {:?}", self.ssh.get_hostname(), e);
-                self.build_empty_metrics()
+        let (indices, queries): (Vec<usize>, Vec<&str>) = self
+            .metric_plugins
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| due[*index] && !self.streamed[*index] && results[*index].is_none())
+            .map(|(index, plugin)| (index, plugin.get_query()))
+            .unzip();
+
+        if !queries.is_empty() {
+            match self.transport.fetch(&queries) {
+                Ok(fetched) => {
+                    let now = SystemTime::now();
+                    // Opening a channel takes a single round trip to the server. The time the
+                    // command was sent at leaves out reconnecting, which would look like skew.
+                    let timings = self.transport.get_timings();
+                    if let Some(sent) = timings.sent {
+                        self.metric_plugins
+                            .iter_mut()
+                            .for_each(|plugin| plugin.request_timing(sent, timings.channel));
+                    }
+
+                    for (index, data) in indices.into_iter().zip(fetched) {
+                        results[index] = Some((data, now));
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        "[{}]: Fetching failed: {}",
+                        self.transport.get_hostname(),
+                        e
+                    );
+                    // The stream keeps the node online, only the plugins that were fetched
+                    // are missing
+                    if self.stream.is_none() {
+                        return self.build_empty_metrics();
+                    }
+                }
             }
         }
+
+        self.run_background(&mut due, &mut results);
+
+        // Counters start over after a reboot, comparing with older samples makes no sense
+        if self.detect_reboot() {
+            info!("[{}] Server has rebooted", self.transport.get_hostname());
+            self.metric_plugins
+                .iter_mut()
+                .for_each(|plugin| plugin.reset());
+            self.events.push(EventKind::Rebooted);
+        }
+
+        let metrics = self.process_results(results, &due);
+        for plugin in self.metric_plugins.iter_mut() {
+            self.events.append(&mut plugin.take_events());
+        }
+        metrics
     }
 
-    fn process_raw_data(&mut self, raw_data: &str) -> NodeMetrics {
-        let (results, _): (Vec<&str>, Vec<&str>) =
-            raw_data.split("######").partition(|s| !s.is_empty());
+    /// Reads the files of the due plugins that need nothing else, every file only once
+    fn read_files(
+        &mut self,
+        due: &[bool],
+        results: &mut [Option<(QueryResult, SystemTime)>],
+    ) -> Result<(), ConnectionError> {
+        let readable: Vec<usize> = (0..due.len())
+            .filter(|&index| {
+                due[index]
+                    && !self.streamed[index]
+                    && !self.metric_plugins[index].files().is_empty()
+            })
+            .collect();
+        if readable.is_empty() {
+            return Ok(());
+        }
+
+        let mut paths: Vec<&str> = vec![];
+        for &index in &readable {
+            for path in self.metric_plugins[index].files() {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        let contents = self.transport.read_files(&paths)?;
+
         let now = SystemTime::now();
+        for index in readable {
+            let data: String = self.metric_plugins[index]
+                .files()
+                .iter()
+                .filter_map(|file| paths.iter().position(|path| path == file))
+                .filter_map(|position| contents.get(position))
+                .map(|content| content.as_str())
+                .collect();
+            results[index] = Some((Ok(data), now));
+        }
+        Ok(())
+    }
+
+    /// Starts the slow queries that are due and collects the results of those that have ended
+    fn run_background(
+        &mut self,
+        due: &mut [bool],
+        results: &mut [Option<(QueryResult, SystemTime)>],
+    ) {
+        for (index, plugin) in self.metric_plugins.iter().enumerate() {
+            let timeout = match plugin.timeout() {
+                Some(timeout) => timeout,
+                None => continue,
+            };
+
+            if let Some(query) = &self.background[index] {
+                if let Some(result) = query.poll() {
+                    results[index] = Some((result, SystemTime::now()));
+                    due[index] = true;
+                    self.background[index] = None;
+                }
+                continue;
+            }
+
+            let state = &mut self.plugin_states[index];
+            if !state.is_due(plugin.interval()) {
+                continue;
+            }
+            // A query that couldn't be started waits for its next turn as well
+            state.last_run = Some(Instant::now());
+            match BackgroundQuery::start(self.transport.as_mut(), plugin.get_query(), timeout) {
+                Ok(query) => self.background[index] = Some(query),
+                Err(e) => debug!(
+                    "[{}]: Starting the query of {} failed: {}",
+                    self.transport.get_hostname(),
+                    state.name,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Returns the latest sample of the stream, or nothing when none arrived since the last tick.
+    /// A stream that stopped delivering samples is started again.
+    fn stream_sample(&mut self) -> Result<Option<Sample>, ConnectionError> {
+        let stall_timeout = match &self.streaming {
+            Some(config) => Duration::from_secs_f64(config.stall_timeout),
+            None => return Ok(None),
+        };
+
+        let stalled = match &self.stream {
+            Some(stream) => stream.is_stalled(stall_timeout),
+            None => true,
+        };
+        if stalled {
+            if self.stream.take().is_some() {
+                warn!(
+                    "[{}] No samples for {:.1} s, restarting the stream",
+                    self.transport.get_hostname(),
+                    stall_timeout.as_secs_f64()
+                );
+            }
+            let queries: Vec<&str> = self
+                .metric_plugins
+                .iter()
+                .zip(self.streamed.iter())
+                .filter(|(_, &streamed)| streamed)
+                .map(|(plugin, _)| plugin.get_query())
+                .collect();
+            self.stream = Some(SampleStream::start(
+                self.transport.as_mut(),
+                &queries,
+                STREAM_INTERVAL,
+            )?);
+        }
+
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        let sample = stream.latest();
+        if !stream.is_alive(stall_timeout) {
+            return Err(ConnectionError::CommandFailed(
+                "The stream has ended".to_string(),
+            ));
+        }
+        Ok(sample)
+    }
+
+    /// Hands every plugin that was queried its part of the output, plugins that were left out
+    /// report their last metrics again. A plugin whose query failed reports nothing.
+    fn process_results(
+        &mut self,
+        results: Vec<Option<(QueryResult, SystemTime)>>,
+        due: &[bool],
+    ) -> NodeMetrics {
+        let hostname = self.transport.get_hostname();
         let mut aggregate = NodeMetrics::default();
         aggregate.online = true;
 
-        self.metric_plugins
+        for (((provider, state), result), &due) in self
+            .metric_plugins
             .iter_mut()
-            .zip(results.iter())
-            .for_each(|(provider, &data)| {
-                aggregate.set(provider.process_data(data, &now));
-            });
+            .zip(self.plugin_states.iter_mut())
+            .zip(results)
+            .zip(due.iter())
+        {
+            if !due {
+                if let Some(metrics) = &state.last_metrics {
+                    aggregate.set(metrics.clone());
+                }
+                continue;
+            }
+
+            let (result, timestamp) = match result {
+                Some(result) => result,
+                None => continue,
+            };
+            // Failing queries are retried at their usual interval as well
+            state.last_run = Some(Instant::now());
+            match result {
+                Ok(data) => {
+                    if state.error.take().is_some() {
+                        info!("[{}] Query of {} works again", hostname, state.name);
+                    }
+                    let metrics = provider.process_data(&data, &timestamp);
+                    state.last_metrics = Some(metrics.clone());
+                    aggregate.set(metrics);
+                }
+                Err(error) => {
+                    if state.error.as_ref() != Some(&error) {
+                        warn!("[{}] Query of {} {}", hostname, state.name, error);
+                    }
+                    state.error = Some(error);
+                    state.last_metrics = None;
+                }
+            }
+        }
 
         aggregate
     }
 
     fn build_empty_metrics(&mut self) -> NodeMetrics {
         let mut metrics = NodeMetrics::default();
-        metrics.hostname = self.ssh.get_hostname().to_string();
+        metrics.hostname = self.transport.get_hostname().to_string();
         metrics
     }
 }
+
+/// Time between two samples of the stream, the aggregator ticks every second
+const STREAM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a plugin was last queried and what it reported then
+struct PluginState {
+    name: String,
+    last_run: Option<Instant>,
+    last_metrics: Option<Metrics>,
+    /// Why the query failed the last time it was run
+    error: Option<QueryError>,
+}
+
+impl PluginState {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            last_run: None,
+            last_metrics: None,
+            error: None,
+        }
+    }
+
+    fn is_due(&self, interval: Duration) -> bool {
+        // Ticks are not exactly a second apart, half a tick of slack keeps plugins that
+        // should run every tick from skipping one
+        match self.last_run {
+            Some(last_run) => last_run.elapsed() + Duration::from_millis(500) >= interval,
+            None => true,
+        }
+    }
+}
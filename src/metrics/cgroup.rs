@@ -0,0 +1,370 @@
+use super::{shell_quote, MetricPlugin, Metrics};
+use crate::config::CgroupConfig;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Cgroups of docker and podman containers that are monitored when no slices are configured
+const CONTAINER_CGROUPS: [&str; 3] = [
+    "system.slice/docker-*.scope",
+    "machine.slice/libpod-*.scope",
+    "user.slice/user-*.slice/user@*.service/user.slice/libpod-*.scope",
+];
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct CgroupMetrics {
+    groups: Vec<CgroupUsage>,
+}
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct CgroupUsage {
+    name: String,
+    cpu_usage: f64,
+    memory_current: f64,
+    memory_max: f64,
+    read_throughput: f64,
+    write_throughput: f64,
+    /// Number of measurements combined into this one, groups come and go between samples
+    #[serde(skip)]
+    samples: f64,
+}
+
+/// Groups are matched by name, so that rollups don't list the same container several times
+impl Add for CgroupMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut groups = self.groups;
+        for group in other.groups {
+            match groups.iter_mut().find(|g| g.name == group.name) {
+                Some(existing) => {
+                    existing.cpu_usage += group.cpu_usage;
+                    existing.memory_current += group.memory_current;
+                    existing.memory_max += group.memory_max;
+                    existing.read_throughput += group.read_throughput;
+                    existing.write_throughput += group.write_throughput;
+                    existing.samples += group.samples;
+                }
+                None => groups.push(group),
+            }
+        }
+        Self { groups }
+    }
+}
+
+impl CgroupMetrics {
+    /// Averages every group over the measurements it appeared in
+    pub fn average(self) -> Self {
+        let groups = self
+            .groups
+            .into_iter()
+            .map(|group| {
+                let divisor = group.samples.max(1.0);
+                CgroupUsage {
+                    name: group.name,
+                    cpu_usage: group.cpu_usage / divisor,
+                    memory_current: group.memory_current / divisor,
+                    memory_max: group.memory_max / divisor,
+                    read_throughput: group.read_throughput / divisor,
+                    write_throughput: group.write_throughput / divisor,
+                    samples: 1.0,
+                }
+            })
+            .collect();
+        Self { groups }
+    }
+
+    /// Counts groups combined across nodes as a single measurement, so that rollups of the
+    /// cluster average over time only
+    pub fn into_single_sample(self) -> Self {
+        let groups = self
+            .groups
+            .into_iter()
+            .map(|group| CgroupUsage {
+                samples: 1.0,
+                ..group
+            })
+            .collect();
+        Self { groups }
+    }
+}
+
+/// Reports CPU, memory and IO usage of individual cgroup v2 groups, e.g. containers
+pub struct CgroupMetricPlugin {
+    command: String,
+    previous_stats: HashMap<String, CgroupStats>,
+}
+
+impl CgroupMetricPlugin {
+    pub fn new(config: &CgroupConfig) -> Self {
+        let patterns: Vec<String> = if config.slices.is_empty() {
+            CONTAINER_CGROUPS.iter().map(|p| p.to_string()).collect()
+        } else {
+            // Quoting everything but the wildcards lets the shell expand globs in slice names
+            config
+                .slices
+                .iter()
+                .map(|slice| {
+                    slice
+                        .split('*')
+                        .map(shell_quote)
+                        .collect::<Vec<_>>()
+                        .join("*")
+                })
+                .collect()
+        };
+
+        let command = format!(
+            // The subshell keeps the directory change from the queries after this one
+            "(cd /sys/fs/cgroup 2>/dev/null && for d in {}; do \
+             [ -f \"$d/cgroup.procs\" ] || continue; \
+             echo \"cgroup $d\"; \
+             grep -w usage_usec \"$d/cpu.stat\"; \
+             echo \"memory.current $(cat \"$d/memory.current\")\"; \
+             echo \"memory.max $(cat \"$d/memory.max\")\"; \
+             if [ -f \"$d/io.stat\" ]; then sed 's/^/io /' \"$d/io.stat\"; fi; \
+             done 2>/dev/null)",
+            patterns.join(" ")
+        );
+
+        Self {
+            command,
+            previous_stats: HashMap::new(),
+        }
+    }
+}
+
+impl MetricPlugin for CgroupMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let stats = CgroupStats::from_string(raw_data, timestamp);
+
+        let mut groups: Vec<CgroupUsage> = stats
+            .iter()
+            .map(|(path, stats)| {
+                let mut usage = CgroupUsage {
+                    name: short_name(path),
+                    memory_current: stats.memory_current as f64,
+                    memory_max: stats.memory_max as f64,
+                    samples: 1.0,
+                    ..CgroupUsage::default()
+                };
+
+                if let Some(previous) = self.previous_stats.get(path) {
+                    let time_elapsed = stats
+                        .current_time
+                        .duration_since(previous.current_time)
+                        .unwrap_or_default()
+                        .as_secs_f64();
+                    if time_elapsed > 0.0 {
+                        let usage_usec = stats.usage_usec.saturating_sub(previous.usage_usec);
+                        let read_bytes = stats.read_bytes.saturating_sub(previous.read_bytes);
+                        let written_bytes =
+                            stats.written_bytes.saturating_sub(previous.written_bytes);
+                        usage.cpu_usage = usage_usec as f64 / 10_000.0 / time_elapsed;
+                        usage.read_throughput = read_bytes as f64 / time_elapsed;
+                        usage.write_throughput = written_bytes as f64 / time_elapsed;
+                    }
+                }
+
+                usage
+            })
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // Replacing the previous stats also forgets containers that are gone
+        self.previous_stats = stats;
+
+        Metrics::Cgroup(CgroupMetrics { groups })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Cgroup(CgroupMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous_stats.clear();
+    }
+}
+
+/// Turns a cgroup path like system.slice/docker-<id>.scope into a readable name
+fn short_name(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name
+        .trim_end_matches(".scope")
+        .trim_end_matches(".slice")
+        .trim_end_matches(".service");
+
+    match name.rsplit_once('-') {
+        Some((prefix, id)) if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) => {
+            format!("{}-{}", prefix, &id[..12])
+        }
+        _ => name.to_string(),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CgroupStats {
+    usage_usec: u64,
+    memory_current: u64,
+    memory_max: u64,
+    read_bytes: u64,
+    written_bytes: u64,
+    current_time: SystemTime,
+}
+
+impl CgroupStats {
+    fn new(current_time: SystemTime) -> Self {
+        Self {
+            usage_usec: 0,
+            memory_current: 0,
+            memory_max: 0,
+            read_bytes: 0,
+            written_bytes: 0,
+            current_time,
+        }
+    }
+
+    /// Parses the stats of all groups in the output, keyed by their cgroup path
+    pub fn from_string(raw_data: &str, timestamp: &SystemTime) -> HashMap<String, Self> {
+        let mut groups = HashMap::new();
+        let mut current = None;
+
+        for line in raw_data.lines() {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+
+            // Paths may contain whitespace, they take up the rest of the line
+            if let Some(path) = line.strip_prefix("cgroup ") {
+                groups.insert(path.to_string(), Self::new(*timestamp));
+                current = Some(path.to_string());
+                continue;
+            }
+
+            let stats = match current.as_ref().and_then(|path| groups.get_mut(path)) {
+                Some(stats) => stats,
+                None => continue,
+            };
+            let number = u64::from_str(value).unwrap_or(0);
+            match key {
+                "usage_usec" => stats.usage_usec = number,
+                "memory.current" => stats.memory_current = number,
+                // "max" means that there is no limit
+                "memory.max" => stats.memory_max = number,
+                "io" => {
+                    for field in parts {
+                        match field.split_once('=') {
+                            Some(("rbytes", v)) => {
+                                stats.read_bytes += u64::from_str(v).unwrap_or(0)
+                            }
+                            Some(("wbytes", v)) => {
+                                stats.written_bytes += u64::from_str(v).unwrap_or(0)
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let id = "4f3c2b1a0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b";
+        let raw_data_1 = format!(
+            "cgroup system.slice/docker-{0}.scope
+usage_usec 1000000
+memory.current 104857600
+memory.max max
+io 8:0 rbytes=4096 wbytes=8192 rios=1 wios=2 dbytes=0 dios=0
+io 8:16 rbytes=4096 wbytes=0 rios=1 wios=0 dbytes=0 dios=0
+cgroup system.slice/nginx.service
+usage_usec 500
+memory.current 1024
+memory.max 2048
+",
+            id
+        );
+        let raw_data_2 = format!(
+            "cgroup system.slice/docker-{0}.scope
+usage_usec 1500000
+memory.current 209715200
+memory.max max
+io 8:0 rbytes=8192 wbytes=16384 rios=2 wios=4 dbytes=0 dios=0
+io 8:16 rbytes=4096 wbytes=0 rios=1 wios=0 dbytes=0 dios=0
+",
+            id
+        );
+        let metrics = CgroupMetrics {
+            groups: vec![CgroupUsage {
+                name: "docker-4f3c2b1a0e9d".to_string(),
+                cpu_usage: 50.0,
+                memory_current: 209715200.,
+                memory_max: 0.,
+                read_throughput: 4096.,
+                write_throughput: 8192.,
+                samples: 1.,
+            }],
+        };
+        assert_parse(&raw_data_1, &raw_data_2, metrics);
+
+        let raw_data = "cgroup system.slice/my app.service\nmemory.current 1024\n";
+        let metrics = CgroupMetrics {
+            groups: vec![CgroupUsage {
+                name: "my app".to_string(),
+                memory_current: 1024.,
+                samples: 1.,
+                ..CgroupUsage::default()
+            }],
+        };
+        assert_parse(raw_data, raw_data, metrics);
+        assert_parse("", "", CgroupMetrics::default());
+    }
+
+    #[test]
+    fn test_average() {
+        let group = |name: &str, memory_current: f64| CgroupUsage {
+            name: name.to_string(),
+            memory_current,
+            samples: 1.,
+            ..CgroupUsage::default()
+        };
+        let first = CgroupMetrics {
+            groups: vec![group("web", 100.), group("worker", 40.)],
+        };
+        let second = CgroupMetrics {
+            groups: vec![group("web", 300.)],
+        };
+
+        // The worker that is missing from the second sample isn't halved
+        let expected = CgroupMetrics {
+            groups: vec![group("web", 200.), group("worker", 40.)],
+        };
+        assert_eq!((first + second).average(), expected);
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: CgroupMetrics) {
+        let mut metric_plugin = CgroupMetricPlugin::new(&CgroupConfig::default());
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Cgroup(expected_metrics));
+    }
+}
@@ -0,0 +1,140 @@
+use super::{MetricPlugin, Metrics};
+use serde_derive::Serialize;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct ClockMetrics {
+    /// Milliseconds that the server clock is ahead (positive) or behind (negative) of ours
+    skew: f64,
+    /// Servers whose clock is not synchronized, unknown states are not counted
+    unsynced: f64,
+}
+
+/// Keeps the largest skew, as the skews of different nodes don't add up
+impl Add for ClockMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            skew: if other.skew.abs() > self.skew.abs() {
+                other.skew
+            } else {
+                self.skew
+            },
+            unsynced: self.unsynced + other.unsynced,
+        }
+    }
+}
+
+impl ClockMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            unsynced: self.unsynced / divisor,
+            ..self
+        }
+    }
+}
+
+/// Compares the server clock with ours and reports whether it is synchronized via NTP
+pub struct ClockMetricPlugin {
+    sent: Option<SystemTime>,
+    round_trip: Duration,
+}
+
+impl ClockMetricPlugin {
+    pub fn new() -> Self {
+        Self {
+            sent: None,
+            round_trip: Duration::default(),
+        }
+    }
+}
+
+impl MetricPlugin for ClockMetricPlugin {
+    fn get_query(&self) -> &'static str {
+        "date +%s.%N && (timedatectl show -p NTPSynchronized --value 2>/dev/null \
+         || chronyc tracking 2>/dev/null | grep 'Leap status'; true)"
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let mut lines = raw_data.lines();
+        let remote_time = lines.next().and_then(|v| f64::from_str(v.trim()).ok());
+
+        // The server reads its clock about half a round trip after we sent the query
+        let local_time = match self.sent.take() {
+            Some(sent) => sent + self.round_trip / 2,
+            None => *timestamp,
+        };
+        let local_time = local_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let skew = remote_time
+            .map(|remote_time| (remote_time - local_time) * 1000.0)
+            .unwrap_or(0.0);
+
+        // timedatectl prints yes or no, chrony reports "Not synchronised" as leap status
+        let unsynced = match lines.next().map(|line| line.trim()) {
+            Some("no") => 1.0,
+            Some(line) if line.ends_with("Not synchronised") => 1.0,
+            _ => 0.0,
+        };
+
+        Metrics::Clock(ClockMetrics { skew, unsynced })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Clock(ClockMetrics::default())
+    }
+
+    fn request_timing(&mut self, sent: SystemTime, round_trip: Duration) {
+        self.sent = Some(sent);
+        self.round_trip = round_trip;
+    }
+
+    fn streamable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_process_data() {
+        let metrics = ClockMetrics {
+            skew: 250.,
+            unsynced: 0.,
+        };
+        assert_parse("1531416624.750000000\nyes\n", metrics);
+
+        let metrics = ClockMetrics {
+            skew: -500.,
+            unsynced: 1.,
+        };
+        assert_parse(
+            "1531416624.000000000\nLeap status     : Not synchronised\n",
+            metrics,
+        );
+
+        let metrics = ClockMetrics {
+            skew: 0.,
+            unsynced: 0.,
+        };
+        assert_parse("1531416624.500000000\n", metrics);
+        assert_parse("", ClockMetrics::default());
+    }
+
+    fn assert_parse(raw_data: &str, expected_metrics: ClockMetrics) {
+        let mut metric_plugin = ClockMetricPlugin::new();
+        let sent = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.request_timing(sent, Duration::from_secs(1));
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data, &now);
+
+        assert_eq!(metrics, Metrics::Clock(expected_metrics));
+    }
+}
@@ -0,0 +1,37 @@
+use crate::transport::ExecTimings;
+use derive_more::Add;
+use serde_derive::Serialize;
+use std::time::Duration;
+
+/// Time in milliseconds that it took to collect the metrics of a node
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Add)]
+pub struct CollectionMetrics {
+    connect_time: f64,
+    channel_time: f64,
+    command_time: f64,
+    total_time: f64,
+}
+
+impl CollectionMetrics {
+    pub fn new(timings: ExecTimings, total: Duration) -> Self {
+        Self {
+            connect_time: milliseconds(timings.connect),
+            channel_time: milliseconds(timings.channel),
+            command_time: milliseconds(timings.command),
+            total_time: milliseconds(total),
+        }
+    }
+
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            connect_time: self.connect_time / divisor,
+            channel_time: self.channel_time / divisor,
+            command_time: self.command_time / divisor,
+            total_time: self.total_time / divisor,
+        }
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
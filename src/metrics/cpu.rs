@@ -48,6 +48,11 @@ impl MetricPlugin for CpuMetricPlugin {
         "grep -w 'cpu' /proc/stat"
     }
 
+    /// The totals are on the first line of the file, which is all the parser looks at
+    fn files(&self) -> Vec<&str> {
+        vec!["/proc/stat"]
+    }
+
     fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
         let cpu_times = CpuTimes::from_string(&raw_data);
         self.cpu.push(cpu_times);
@@ -67,6 +72,10 @@ impl MetricPlugin for CpuMetricPlugin {
     fn empty_metrics(&self) -> Metrics {
         Metrics::Cpu(CpuMetrics::default())
     }
+
+    fn reset(&mut self) {
+        self.cpu.reset();
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -175,34 +184,26 @@ impl CpuTimes {
         self.work() + self.idle + self.iowait
     }
 
-    pub fn diff(&self, other: &Self) -> Self {
-        macro_rules! diff {
-            ($this:expr, $that:expr) => {
-                if $this > $that {
-                    $this - $that
-                } else {
-                    $that - $this
-                }
-            };
-        }
-        Self::new(
-            diff!(self.user, other.user),
-            diff!(self.nice, other.nice),
-            diff!(self.system, other.system),
-            diff!(self.idle, other.idle),
-            diff!(self.iowait, other.iowait),
-            diff!(self.irq, other.irq),
-            diff!(self.softirq, other.softirq),
-            diff!(self.steal, other.steal),
-            diff!(self.guest, other.guest),
-            diff!(self.guest_nice, other.guest_nice),
-        )
+    /// Returns None when any of the counters went backwards, e.g. after a reboot
+    pub fn diff(&self, previous: &Self) -> Option<Self> {
+        Some(Self::new(
+            self.user.checked_sub(previous.user)?,
+            self.nice.checked_sub(previous.nice)?,
+            self.system.checked_sub(previous.system)?,
+            self.idle.checked_sub(previous.idle)?,
+            self.iowait.checked_sub(previous.iowait)?,
+            self.irq.checked_sub(previous.irq)?,
+            self.softirq.checked_sub(previous.softirq)?,
+            self.steal.checked_sub(previous.steal)?,
+            self.guest.checked_sub(previous.guest)?,
+            self.guest_nice.checked_sub(previous.guest_nice)?,
+        ))
     }
 }
 
 #[derive(Default)]
 pub struct Cpu {
-    last_cpu_times: CpuTimes,
+    last_cpu_times: Option<CpuTimes>,
     user_percent: f32,
     nice_percent: f32,
     system_percent: f32,
@@ -247,8 +248,21 @@ impl Cpu {
     }
 
 
+    pub fn reset(&mut self) {
+        self.last_cpu_times = None;
+    }
+
     pub fn push(&mut self, cpu_times: CpuTimes) {
-        let diff = cpu_times.diff(&self.last_cpu_times);
+        let diff = match self.last_cpu_times.replace(cpu_times) {
+            Some(last_cpu_times) => match cpu_times.diff(&last_cpu_times) {
+                Some(diff) => diff,
+                // A sample after a counter reset is discarded, the percentages of the one before
+                // are reported again
+                None => return,
+            },
+            // The first sample only serves as a baseline
+            None => CpuTimes::default(),
+        };
 
         let total = diff.total() as f32;
 
@@ -271,8 +285,6 @@ impl Cpu {
             self.softirq_percent = diff.softirq() as f32 / total * 100.0;
             self.work_percent = diff.work() as f32 / total * 100.0;
         }
-
-        self.last_cpu_times = cpu_times;
     }
 }
 
@@ -310,6 +322,18 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_counter_reset() {
+        let raw_data_1 = "cpu  360767 1051 58366 6829700 12458 0 861 0 0 0";
+        let raw_data_2 = "cpu  1180 3 402 7200 51 0 9 0 0 0";
+        let mut metric_plugin = CpuMetricPlugin::new();
+        let now = SystemTime::now();
+        metric_plugin.process_data("cpu  350732 1048 57727 6753933 12435 0 859 0 0 0", &now);
+        let metrics = metric_plugin.process_data(raw_data_1, &now);
+
+        assert_eq!(metric_plugin.process_data(raw_data_2, &now), metrics);
+    }
+
     fn assert_parse(
         raw_data_1: &str,
         raw_data_2: &str,
@@ -0,0 +1,314 @@
+use super::{MetricPlugin, Metrics};
+use crate::config::{Aggregation, CustomMetricConfig, CustomParser};
+use serde_derive::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct CustomMetrics {
+    #[serde(skip)]
+    name: String,
+    unit: String,
+    values: BTreeMap<String, f64>,
+    #[serde(skip)]
+    aggregation: Aggregation,
+    /// Number of measurements that were combined into every value, not every node or sample
+    /// reports all keys
+    #[serde(skip)]
+    counts: BTreeMap<String, f64>,
+}
+
+impl Add for CustomMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let aggregation = self.aggregation;
+        let mut values = self.values;
+        let mut counts = self.counts;
+        for (key, count) in other.counts {
+            *counts.entry(key).or_insert(0.0) += count;
+        }
+        for (key, value) in other.values {
+            let combined = match values.get(&key) {
+                Some(&existing) => match aggregation {
+                    Aggregation::Sum | Aggregation::Avg => existing + value,
+                    Aggregation::Max => existing.max(value),
+                    Aggregation::Min => existing.min(value),
+                },
+                None => value,
+            };
+            values.insert(key, combined);
+        }
+
+        Self {
+            values,
+            counts,
+            ..self
+        }
+    }
+}
+
+impl CustomMetrics {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Averages measurements taken over time, extremes are kept as they are
+    pub fn average(self) -> Self {
+        match self.aggregation {
+            Aggregation::Sum | Aggregation::Avg => self.divide_values(),
+            Aggregation::Max | Aggregation::Min => self.single_sample(),
+        }
+    }
+
+    /// Applies the configured aggregation rule to measurements combined across nodes
+    pub fn aggregate_nodes(self) -> Self {
+        match self.aggregation {
+            Aggregation::Avg => self.divide_values(),
+            _ => self.single_sample(),
+        }
+    }
+
+    /// Divides every value by the number of measurements it was reported in
+    fn divide_values(mut self) -> Self {
+        for (key, value) in self.values.iter_mut() {
+            *value /= self.counts.get(key).copied().unwrap_or(1.0).max(1.0);
+        }
+        self.single_sample()
+    }
+
+    /// Counts the combined values as one measurement, for the rollups that follow
+    fn single_sample(mut self) -> Self {
+        self.counts.values_mut().for_each(|count| *count = 1.0);
+        self
+    }
+}
+
+/// Runs a user-defined command and turns its output into metrics
+pub struct CustomMetricPlugin {
+    config: CustomMetricConfig,
+    previous_values: Option<(BTreeMap<String, f64>, SystemTime)>,
+}
+
+impl CustomMetricPlugin {
+    pub fn new(config: &CustomMetricConfig) -> Self {
+        Self {
+            config: config.clone(),
+            previous_values: None,
+        }
+    }
+
+    fn empty(&self) -> CustomMetrics {
+        CustomMetrics {
+            name: self.config.name.clone(),
+            unit: self.config.unit.clone(),
+            values: BTreeMap::new(),
+            aggregation: self.config.aggregation,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    fn parse(&self, raw_data: &str) -> BTreeMap<String, f64> {
+        let mut values = BTreeMap::new();
+        match self.config.parser {
+            CustomParser::Number => {
+                if let Some(value) = raw_data
+                    .split_whitespace()
+                    .next()
+                    .and_then(|v| f64::from_str(v).ok())
+                {
+                    values.insert("value".to_string(), value);
+                }
+            }
+            CustomParser::KeyValue => {
+                raw_data
+                    .lines()
+                    .filter_map(|line| line.split_once('='))
+                    .for_each(|(key, value)| {
+                        if let Ok(value) = f64::from_str(value.trim()) {
+                            values.insert(key.trim().to_string(), value);
+                        }
+                    });
+            }
+            CustomParser::Json => {
+                if let Ok(json) = serde_json::from_str::<Value>(raw_data) {
+                    flatten_json("", &json, &mut values);
+                }
+            }
+        }
+        values
+    }
+
+    /// Converts ever-growing counters into per second rates
+    fn rates(
+        &mut self,
+        values: BTreeMap<String, f64>,
+        timestamp: &SystemTime,
+    ) -> BTreeMap<String, f64> {
+        let rates = match &self.previous_values {
+            Some((previous_values, previous_time)) => {
+                let time_elapsed = timestamp
+                    .duration_since(*previous_time)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                values
+                    .iter()
+                    .map(|(key, &value)| {
+                        let rate = match previous_values.get(key) {
+                            // A counter that went backwards has been reset
+                            Some(&previous) if time_elapsed > 0.0 && value >= previous => {
+                                (value - previous) / time_elapsed
+                            }
+                            _ => 0.0,
+                        };
+                        (key.clone(), rate)
+                    })
+                    .collect()
+            }
+            None => values.keys().map(|key| (key.clone(), 0.0)).collect(),
+        };
+
+        self.previous_values = Some((values, *timestamp));
+        rates
+    }
+}
+
+/// Collects all numbers of a JSON document, nested keys are joined with dots
+fn flatten_json(prefix: &str, json: &Value, values: &mut BTreeMap<String, f64>) {
+    let name = if prefix.is_empty() { "value" } else { prefix };
+
+    match json {
+        Value::Object(map) => map.iter().for_each(|(key, value)| {
+            let key = if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            flatten_json(&key, value, values)
+        }),
+        Value::Number(number) => {
+            values.insert(name.to_string(), number.as_f64().unwrap_or(0.0));
+        }
+        Value::Bool(flag) => {
+            values.insert(name.to_string(), if *flag { 1.0 } else { 0.0 });
+        }
+        _ => (),
+    }
+}
+
+impl MetricPlugin for CustomMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.config.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let mut values = self.parse(raw_data);
+        if self.config.counter {
+            values = self.rates(values, timestamp);
+        }
+
+        Metrics::Custom(CustomMetrics {
+            counts: values.keys().map(|key| (key.clone(), 1.0)).collect(),
+            values,
+            ..self.empty()
+        })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Custom(self.empty())
+    }
+
+    fn reset(&mut self) {
+        self.previous_values = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        assert_parse(CustomParser::Number, false, "", "42\n", &[("value", 42.)]);
+        assert_parse(
+            CustomParser::KeyValue,
+            false,
+            "",
+            "ready = 12\nunacked=3\nstate=running\n",
+            &[("ready", 12.), ("unacked", 3.)],
+        );
+        assert_parse(
+            CustomParser::Json,
+            false,
+            "",
+            r#"{"queues": {"jobs": 7, "mail": 1}, "healthy": true, "version": "1.2"}"#,
+            &[("healthy", 1.), ("queues.jobs", 7.), ("queues.mail", 1.)],
+        );
+        assert_parse(
+            CustomParser::KeyValue,
+            true,
+            "requests=1000\nerrors=10",
+            "requests=1250\nerrors=10",
+            &[("errors", 0.), ("requests", 250.)],
+        );
+        assert_parse(CustomParser::Json, false, "", "not json", &[]);
+    }
+
+    #[test]
+    fn test_average() {
+        let measurement = |values: &[(&str, f64)]| CustomMetrics {
+            name: "app".to_string(),
+            unit: "".to_string(),
+            values: values.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            aggregation: Aggregation::Avg,
+            counts: values.iter().map(|(k, _)| (k.to_string(), 1.)).collect(),
+        };
+        let combined = measurement(&[("jobs", 10.), ("mail", 4.)]) + measurement(&[("jobs", 20.)]);
+
+        // A key that only one node reports isn't halved
+        assert_eq!(
+            combined.aggregate_nodes(),
+            measurement(&[("jobs", 15.), ("mail", 4.)])
+        );
+    }
+
+    fn assert_parse(
+        parser: CustomParser,
+        counter: bool,
+        raw_data_1: &str,
+        raw_data_2: &str,
+        expected_values: &[(&str, f64)],
+    ) {
+        let config = CustomMetricConfig {
+            name: "app".to_string(),
+            command: "true".to_string(),
+            parser,
+            unit: "".to_string(),
+            counter,
+            aggregation: Aggregation::Sum,
+        };
+        let mut metric_plugin = CustomMetricPlugin::new(&config);
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        let expected_metrics = Metrics::Custom(CustomMetrics {
+            values: expected_values
+                .iter()
+                .map(|(key, value)| (key.to_string(), *value))
+                .collect(),
+            counts: expected_values
+                .iter()
+                .map(|(key, _)| (key.to_string(), 1.))
+                .collect(),
+            ..metric_plugin.empty()
+        });
+
+        assert_eq!(metrics, expected_metrics);
+    }
+}
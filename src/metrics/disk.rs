@@ -1,4 +1,4 @@
-use super::{MetricPlugin, Metrics};
+use super::{counter_rate, MetricPlugin, Metrics};
 use derive_more::Add;
 use serde_derive::Serialize;
 use std::str::FromStr;
@@ -22,13 +22,19 @@ impl DiskMetrics {
 pub struct DiskMetricPlugin {
     disk: Disk,
     command: String,
+    path: String,
 }
 
 impl DiskMetricPlugin {
     pub fn new(device: &str) -> Self {
         let disk = Disk::default();
-        let command = format!("cat /sys/block/{}/stat", device);
-        Self { disk, command }
+        let path = format!("/sys/block/{}/stat", device);
+        let command = format!("cat {}", path);
+        Self {
+            disk,
+            command,
+            path,
+        }
     }
 }
 
@@ -37,6 +43,10 @@ impl MetricPlugin for DiskMetricPlugin {
         &self.command
     }
 
+    fn files(&self) -> Vec<&str> {
+        vec![&self.path]
+    }
+
     fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
         let disk_stats = DiskStats::from_string(&raw_data, timestamp);
 
@@ -51,6 +61,10 @@ impl MetricPlugin for DiskMetricPlugin {
     fn empty_metrics(&self) -> Metrics {
         Metrics::Disk(DiskMetrics::default())
     }
+
+    fn reset(&mut self) {
+        self.disk.reset();
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -150,44 +164,47 @@ impl DiskStats {
 
 #[derive(Default)]
 pub struct Disk {
-    previous_disk_stats: DiskStats,
+    previous_disk_stats: Option<DiskStats>,
     read_throughput: f64,
     write_throughput: f64,
 }
 
 impl Disk {
-    pub fn push(&mut self, disk_stats: DiskStats) {
-        macro_rules! diff {
-            ($this:expr, $that:expr) => {
-                if $this > $that {
-                    $this - $that
-                } else {
-                    $that - $this
-                }
-            };
-        }
+    pub fn reset(&mut self) {
+        self.previous_disk_stats = None;
+    }
 
+    pub fn push(&mut self, disk_stats: DiskStats) {
+        // The first sample only serves as a baseline
+        let previous = match self.previous_disk_stats.replace(disk_stats) {
+            Some(previous) => previous,
+            None => {
+                self.read_throughput = 0.0;
+                self.write_throughput = 0.0;
+                return;
+            }
+        };
         let time_elapsed = disk_stats
             .current_time()
-            .duration_since(self.previous_disk_stats.current_time())
-            .expect("There is a bug in elapsed time calculation");
-        let time_elapsed =
-            time_elapsed.as_secs() as f64 + time_elapsed.subsec_millis() as f64 / 1000.0;
+            .duration_since(previous.current_time())
+            .unwrap_or_default()
+            .as_secs_f64();
 
-        let sectors_read = diff!(
+        // The throughput from before a counter reset is reported again
+        if let Some(sectors_read) = counter_rate(
             disk_stats.sectors_read(),
-            self.previous_disk_stats.sectors_read()
-        ) as f64;
-
-        let sectors_written = diff!(
+            previous.sectors_read(),
+            time_elapsed,
+        ) {
+            self.read_throughput = sectors_read * 512.0;
+        }
+        if let Some(sectors_written) = counter_rate(
             disk_stats.sectors_written(),
-            self.previous_disk_stats.sectors_written()
-        ) as f64;
-
-        self.read_throughput = sectors_read * 512.0 / time_elapsed;
-        self.write_throughput = sectors_written * 512.0 / time_elapsed;
-
-        self.previous_disk_stats = disk_stats;
+            previous.sectors_written(),
+            time_elapsed,
+        ) {
+            self.write_throughput = sectors_written * 512.0;
+        }
     }
 
     pub fn read_throughput(&self) -> f64 {
@@ -214,6 +231,27 @@ mod test {
         assert_parse("", "", 0.0, 0.0);
     }
 
+    #[test]
+    fn test_counter_reset() {
+        let raw_data_1 = "  255586     4852  7024174   115692    31086    50639  3211504   132760        0    48784   248760";
+        let raw_data_2 = "  255600     4852  7027286   115700    31108    50799  3213280   132824        0    48852   248832";
+        let raw_data_3 = "  1030     12  3286   900    108    99  1280   824        0    852   832";
+        assert_reset(raw_data_1, raw_data_2, raw_data_3);
+    }
+
+    /// The sample after a reset reports the same throughput as the one before
+    fn assert_reset(raw_data_1: &str, raw_data_2: &str, raw_data_3: &str) {
+        let mut metric_plugin = DiskMetricPlugin::new("sda");
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416626, 0);
+
+        assert_ne!(metrics, metric_plugin.empty_metrics());
+        assert_eq!(metric_plugin.process_data(raw_data_3, &now), metrics);
+    }
+
     fn assert_parse(
         raw_data_1: &str,
         raw_data_2: &str,
@@ -0,0 +1,38 @@
+use super::ports::ListeningSocket;
+use actix::prelude::*;
+use serde_derive::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Something that happened on a node, as opposed to a value that is measured continuously
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind")]
+pub enum EventKind {
+    Rebooted,
+    OomKill { count: u64 },
+    PortOpened(ListeningSocket),
+    PortClosed(ListeningSocket),
+}
+
+#[derive(Clone, Debug, Serialize, Message)]
+#[rtype(result = "()")]
+pub struct NodeEvent {
+    hostname: String,
+    /// Unix timestamp of the moment the event was noticed
+    timestamp: u64,
+    #[serde(flatten)]
+    kind: EventKind,
+}
+
+impl NodeEvent {
+    pub fn new(hostname: String, kind: EventKind) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        Self {
+            hostname,
+            timestamp,
+            kind,
+        }
+    }
+}
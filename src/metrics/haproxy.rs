@@ -0,0 +1,186 @@
+use super::{counter_rate, shell_quote, MetricPlugin, Metrics};
+use crate::config::HaproxyConfig;
+use derive_more::Add;
+use serde_derive::Serialize;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Add)]
+pub struct HaproxyMetrics {
+    /// Sessions of all frontends
+    current_sessions: f64,
+    sessions_per_second: f64,
+    /// Responses with a 5xx status sent by the frontends per second
+    errors_per_second: f64,
+    /// Backend servers that are down or don't take new sessions (NOLB). Servers without a health
+    /// check, draining and disabled ones are not counted.
+    servers_down: f64,
+}
+
+impl HaproxyMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            current_sessions: self.current_sessions / divisor,
+            sessions_per_second: self.sessions_per_second / divisor,
+            errors_per_second: self.errors_per_second / divisor,
+            servers_down: self.servers_down / divisor,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct HaproxyCounters {
+    sessions: u64,
+    errors: u64,
+}
+
+/// Reads session counters and server states from the CSV of the HAProxy stats socket
+pub struct HaproxyMetricPlugin {
+    command: String,
+    previous: Option<(HaproxyCounters, SystemTime)>,
+    /// Reported for the previous sample, its rates are reported again after a counter reset
+    last_metrics: HaproxyMetrics,
+}
+
+impl HaproxyMetricPlugin {
+    pub fn new(config: &HaproxyConfig) -> Self {
+        let command = format!(
+            "echo 'show stat' | socat stdio {} 2>/dev/null",
+            shell_quote(&format!("UNIX-CONNECT:{}", config.socket))
+        );
+        Self {
+            command,
+            previous: None,
+            last_metrics: HaproxyMetrics::default(),
+        }
+    }
+}
+
+impl MetricPlugin for HaproxyMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let mut lines = raw_data.lines();
+        // The header names the columns, e.g. "# pxname,svname,qcur,qmax,scur,..."
+        let header: Vec<&str> = match lines.next().and_then(|h| h.strip_prefix("# ")) {
+            Some(header) => header.split(',').collect(),
+            None => return self.empty_metrics(),
+        };
+        let column = |name: &str| header.iter().position(|c| *c == name);
+        let (svname, scur, stot, hrsp_5xx, status) = (
+            column("svname"),
+            column("scur"),
+            column("stot"),
+            column("hrsp_5xx"),
+            column("status"),
+        );
+
+        let mut metrics = HaproxyMetrics::default();
+        let mut counters = HaproxyCounters {
+            sessions: 0,
+            errors: 0,
+        };
+        for line in lines {
+            let fields: Vec<&str> = line.split(',').collect();
+            let field = |index: Option<usize>| index.and_then(|i| fields.get(i)).cloned();
+            let number = |index: Option<usize>| {
+                field(index)
+                    .and_then(|v| u64::from_str(v).ok())
+                    .unwrap_or(0)
+            };
+
+            match field(svname) {
+                Some("FRONTEND") => {
+                    metrics.current_sessions += number(scur) as f64;
+                    counters.sessions += number(stot);
+                    counters.errors += number(hrsp_5xx);
+                }
+                Some("BACKEND") | None => (),
+                Some(_) => {
+                    let status = field(status).unwrap_or("");
+                    // Transitional states like "DOWN 1/2" are still counted as down
+                    if status.starts_with("DOWN") || status == "NOLB" {
+                        metrics.servers_down += 1.0;
+                    }
+                }
+            }
+        }
+
+        if let Some((previous, previous_time)) = self.previous {
+            let time_elapsed = timestamp
+                .duration_since(previous_time)
+                .unwrap_or_default()
+                .as_secs_f64();
+            metrics.sessions_per_second =
+                counter_rate(counters.sessions, previous.sessions, time_elapsed)
+                    .unwrap_or(self.last_metrics.sessions_per_second);
+            metrics.errors_per_second =
+                counter_rate(counters.errors, previous.errors, time_elapsed)
+                    .unwrap_or(self.last_metrics.errors_per_second);
+        }
+        self.previous = Some((counters, *timestamp));
+        self.last_metrics = metrics.clone();
+
+        Metrics::Haproxy(metrics)
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Haproxy(HaproxyMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+        self.last_metrics = HaproxyMetrics::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 = "# pxname,svname,qcur,qmax,scur,smax,slim,stot,status,hrsp_5xx,
+http,FRONTEND,,,12,40,2000,5000,OPEN,10,
+app,web1,0,0,6,20,,2500,UP,,
+app,web2,0,0,0,20,,2400,DOWN,,
+app,web3,0,0,0,0,,0,MAINT,,
+app,web4,0,0,0,10,,100,no check,,
+app,web5,0,0,0,10,,100,DRAIN,,
+app,BACKEND,0,0,6,40,200,4900,UP,10,
+";
+        let raw_data_2 = "# pxname,svname,qcur,qmax,scur,smax,slim,stot,status,hrsp_5xx,
+http,FRONTEND,,,15,40,2000,5100,OPEN,12,
+app,web1,0,0,15,20,,2600,UP,,
+app,web2,0,0,0,20,,2400,DOWN,,
+app,web3,0,0,0,0,,0,MAINT,,
+app,web4,0,0,0,10,,100,no check,,
+app,web5,0,0,0,10,,100,DRAIN,,
+app,BACKEND,0,0,15,40,200,5000,UP,12,
+";
+        let metrics = HaproxyMetrics {
+            current_sessions: 15.,
+            sessions_per_second: 100.,
+            errors_per_second: 2.,
+            servers_down: 1.,
+        };
+        assert_parse(raw_data_1, raw_data_2, metrics);
+        assert_parse("", "", HaproxyMetrics::default());
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: HaproxyMetrics) {
+        let config = HaproxyConfig {
+            socket: "/run/haproxy/admin.sock".to_string(),
+        };
+        let mut metric_plugin = HaproxyMetricPlugin::new(&config);
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Haproxy(expected_metrics));
+    }
+}
@@ -1,12 +1,16 @@
 use super::metric_buffer::{MetricBuffer, MetricBufferMap};
 use crate::metrics::aggregator::{Node, NodeMetrics, NodeSpecs};
+use crate::metrics::event::NodeEvent;
 use crate::ws::server::MessageData::*;
 use crate::ws::server::{ClientJoined, OutboundMessage, Receiver, View, WsServer};
 use actix::prelude::*;
 use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
 
+/// Number of recent events that are kept for clients that connect later
+const EVENT_HISTORY: usize = 100;
+
 /// Metric Hub
 ///
 /// It receives metrics from the MetricAggregator, stores them and provides to clients
@@ -17,6 +21,7 @@ pub struct MetricHub {
     node_specs: HashMap<String, NodeSpecs>,
     cluster_specs: NodeSpecs,
     latest_metrics: HashMap<String, NodeMetrics>,
+    events: VecDeque<NodeEvent>,
 }
 
 impl Default for MetricHub {
@@ -28,6 +33,7 @@ impl Default for MetricHub {
             node_specs: HashMap::new(),
             cluster_specs: NodeSpecs::new(0, "Cluster".to_string(), 0, "".to_string()),
             latest_metrics: HashMap::new(),
+            events: VecDeque::with_capacity(EVENT_HISTORY),
         }
     }
 }
@@ -151,6 +157,14 @@ impl MetricHub {
         }
     }
 
+    /// Events are rare, so the whole history is sent and clients simply replace what they show
+    fn send_events(&self, receiver: Receiver) {
+        self.send_to_server(OutboundMessage {
+            receiver,
+            data: NodeEvents(self.events.iter().cloned().collect()),
+        });
+    }
+
     fn send_cluster_history(
         &mut self,
         receiver_id: usize,
@@ -208,5 +222,18 @@ impl Handler<ClientJoined> for MetricHub {
         self.ws_server = Some(msg.ws_server);
         self.send_node_history(msg.session_id, msg.subscribe_to.clone(), ctx);
         self.send_cluster_history(msg.session_id, msg.subscribe_to.clone(), ctx);
+        self.send_events(Receiver::Only(msg.session_id));
+    }
+}
+
+impl Handler<NodeEvent> for MetricHub {
+    type Result = ();
+
+    fn handle(&mut self, event: NodeEvent, _: &mut Context<Self>) {
+        if self.events.len() == EVENT_HISTORY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        self.send_events(Receiver::Everyone);
     }
 }
@@ -0,0 +1,280 @@
+use super::{counter_rate, event::EventKind, MetricPlugin, Metrics};
+use derive_more::Add;
+use serde_derive::Serialize;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Add)]
+pub struct KernelMetrics {
+    context_switches: f64,
+    interrupts: f64,
+    forks: f64,
+    procs_running: f64,
+    procs_blocked: f64,
+}
+
+impl KernelMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            context_switches: self.context_switches / divisor,
+            interrupts: self.interrupts / divisor,
+            forks: self.forks / divisor,
+            procs_running: self.procs_running / divisor,
+            procs_blocked: self.procs_blocked / divisor,
+        }
+    }
+}
+
+/// Reports scheduler activity from the counters at the bottom of /proc/stat
+pub struct KernelMetricPlugin {
+    kernel: Kernel,
+}
+
+impl KernelMetricPlugin {
+    pub fn new() -> Self {
+        Self {
+            kernel: Kernel::default(),
+        }
+    }
+}
+
+impl MetricPlugin for KernelMetricPlugin {
+    fn get_query(&self) -> &'static str {
+        // The intr line lists every interrupt individually, only the total is needed
+        "awk '/^(ctxt|intr|processes|procs_running|procs_blocked|oom_kill) / {print $1, $2}' \
+         /proc/stat /proc/vmstat"
+    }
+
+    /// Read in full, the parser skips the lines it has no use for
+    fn files(&self) -> Vec<&str> {
+        vec!["/proc/stat", "/proc/vmstat"]
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let kernel_stats = KernelStats::from_string(raw_data, timestamp);
+
+        self.kernel.push(kernel_stats);
+
+        Metrics::Kernel(KernelMetrics {
+            context_switches: self.kernel.context_switches(),
+            interrupts: self.kernel.interrupts(),
+            forks: self.kernel.forks(),
+            procs_running: kernel_stats.procs_running() as f64,
+            procs_blocked: kernel_stats.procs_blocked() as f64,
+        })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Kernel(KernelMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.kernel.reset();
+    }
+
+    fn take_events(&mut self) -> Vec<EventKind> {
+        match self.kernel.take_oom_kills() {
+            0 => vec![],
+            count => vec![EventKind::OomKill { count }],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KernelStats {
+    context_switches: u64,
+    interrupts: u64,
+    processes: u64,
+    procs_running: u64,
+    procs_blocked: u64,
+    oom_kills: u64,
+    current_time: SystemTime,
+}
+
+impl Default for KernelStats {
+    fn default() -> Self {
+        Self {
+            context_switches: 0,
+            interrupts: 0,
+            processes: 0,
+            procs_running: 0,
+            procs_blocked: 0,
+            oom_kills: 0,
+            current_time: UNIX_EPOCH,
+        }
+    }
+}
+
+impl KernelStats {
+    pub fn from_string(raw_data: &str, timestamp: &SystemTime) -> Self {
+        let mut stats = Self {
+            current_time: *timestamp,
+            ..Self::default()
+        };
+
+        raw_data.lines().for_each(|line| {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or("");
+            let value = parts
+                .next()
+                .and_then(|v| u64::from_str(v).ok())
+                .unwrap_or(0);
+            match key {
+                "ctxt" => stats.context_switches = value,
+                "intr" => stats.interrupts = value,
+                "processes" => stats.processes = value,
+                "procs_running" => stats.procs_running = value,
+                "procs_blocked" => stats.procs_blocked = value,
+                "oom_kill" => stats.oom_kills = value,
+                _ => (),
+            }
+        });
+
+        stats
+    }
+
+    pub fn procs_running(&self) -> u64 {
+        self.procs_running
+    }
+
+    pub fn procs_blocked(&self) -> u64 {
+        self.procs_blocked
+    }
+}
+
+#[derive(Default)]
+pub struct Kernel {
+    previous_kernel_stats: Option<KernelStats>,
+    context_switches: f64,
+    interrupts: f64,
+    forks: f64,
+    /// Processes killed by the OOM killer that haven't been reported yet
+    oom_kills: u64,
+}
+
+impl Kernel {
+    pub fn reset(&mut self) {
+        self.previous_kernel_stats = None;
+    }
+
+    pub fn push(&mut self, kernel_stats: KernelStats) {
+        // The first sample only serves as a baseline
+        let previous = match self.previous_kernel_stats.replace(kernel_stats) {
+            Some(previous) => previous,
+            None => {
+                self.context_switches = 0.0;
+                self.interrupts = 0.0;
+                self.forks = 0.0;
+                return;
+            }
+        };
+        let time_elapsed = kernel_stats
+            .current_time
+            .duration_since(previous.current_time)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        // The rates from before a counter reset are reported again
+        if let Some(rate) = counter_rate(
+            kernel_stats.context_switches,
+            previous.context_switches,
+            time_elapsed,
+        ) {
+            self.context_switches = rate;
+        }
+        if let Some(rate) = counter_rate(kernel_stats.interrupts, previous.interrupts, time_elapsed)
+        {
+            self.interrupts = rate;
+        }
+        if let Some(rate) = counter_rate(kernel_stats.processes, previous.processes, time_elapsed) {
+            self.forks = rate;
+        }
+        self.oom_kills += kernel_stats.oom_kills.saturating_sub(previous.oom_kills);
+    }
+
+    pub fn take_oom_kills(&mut self) -> u64 {
+        std::mem::take(&mut self.oom_kills)
+    }
+
+    pub fn context_switches(&self) -> f64 {
+        self.context_switches
+    }
+
+    pub fn interrupts(&self) -> f64 {
+        self.interrupts
+    }
+
+    pub fn forks(&self) -> f64 {
+        self.forks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 =
+            "intr 1459226093\nctxt 2717364932\nprocesses 3016447\nprocs_running 2\nprocs_blocked 0";
+        let raw_data_2 =
+            "intr 1459229187\nctxt 2717370114\nprocesses 3016452\nprocs_running 5\nprocs_blocked 3";
+        let metrics = KernelMetrics {
+            context_switches: 2717370114. - 2717364932.,
+            interrupts: 1459229187. - 1459226093.,
+            forks: 5.,
+            procs_running: 5.,
+            procs_blocked: 3.,
+        };
+        assert_parse(raw_data_1, raw_data_2, metrics);
+        assert_parse("", "", KernelMetrics::default());
+    }
+
+    #[test]
+    fn test_counter_reset() {
+        let mut metric_plugin = KernelMetricPlugin::new();
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(
+            "intr 1459226093\nctxt 2717364932\nprocesses 3016447\noom_kill 2",
+            &now,
+        );
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(
+            "intr 1459229187\nctxt 2717370114\nprocesses 3016452\noom_kill 2",
+            &now,
+        );
+        let now = UNIX_EPOCH + Duration::new(1531416626, 0);
+
+        // The sample after the reset reports the rates of the one before, but no OOM kills
+        let raw_data = "intr 30187\nctxt 51220\nprocesses 912\noom_kill 0";
+        assert_eq!(metric_plugin.process_data(raw_data, &now), metrics);
+        assert_eq!(metric_plugin.take_events(), vec![]);
+    }
+
+    #[test]
+    fn test_oom_kill_events() {
+        let mut metric_plugin = KernelMetricPlugin::new();
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data("ctxt 2717364932\noom_kill 2", &now);
+        assert_eq!(metric_plugin.take_events(), vec![]);
+
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        metric_plugin.process_data("ctxt 2717370114\noom_kill 5", &now);
+        assert_eq!(
+            metric_plugin.take_events(),
+            vec![EventKind::OomKill { count: 3 }]
+        );
+        assert_eq!(metric_plugin.take_events(), vec![]);
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: KernelMetrics) {
+        let mut metric_plugin = KernelMetricPlugin::new();
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Kernel(expected_metrics));
+    }
+}
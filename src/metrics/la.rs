@@ -30,6 +30,10 @@ impl MetricPlugin for LoadAverageMetricPlugin {
         "cat /proc/loadavg"
     }
 
+    fn files(&self) -> Vec<&str> {
+        vec!["/proc/loadavg"]
+    }
+
     fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
         let (parts, _): (Vec<&str>, Vec<&str>) =
             raw_data.split_whitespace().partition(|s| !s.is_empty());
@@ -0,0 +1,121 @@
+use super::{MetricPlugin, Metrics};
+use derive_more::Add;
+use serde_derive::Serialize;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Add)]
+pub struct LimitsMetrics {
+    fd_used: f64,
+    fd_max: f64,
+    conntrack_used: f64,
+    conntrack_max: f64,
+}
+
+impl LimitsMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            fd_used: self.fd_used / divisor,
+            fd_max: self.fd_max / divisor,
+            conntrack_used: self.conntrack_used / divisor,
+            conntrack_max: self.conntrack_max / divisor,
+        }
+    }
+}
+
+/// Reports usage of kernel tables that have a hard ceiling: open files and tracked connections
+pub struct LimitsMetricPlugin {}
+
+impl LimitsMetricPlugin {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl MetricPlugin for LimitsMetricPlugin {
+    fn get_query(&self) -> &'static str {
+        // Conntrack counters only exist while the nf_conntrack module is loaded
+        "cat /proc/sys/fs/file-nr && if [ -e /proc/sys/net/netfilter/nf_conntrack_count ]; then \
+         cat /proc/sys/net/netfilter/nf_conntrack_count /proc/sys/net/netfilter/nf_conntrack_max; \
+         fi"
+    }
+
+    fn files(&self) -> Vec<&str> {
+        vec![
+            "/proc/sys/fs/file-nr",
+            "/proc/sys/net/netfilter/nf_conntrack_count",
+            "/proc/sys/net/netfilter/nf_conntrack_max",
+        ]
+    }
+
+    fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
+        let mut lines = raw_data.lines().filter(|line| !line.trim().is_empty());
+
+        let file_nr: Vec<u64> = lines
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .map(|v| u64::from_str(v).unwrap_or(0))
+            .collect();
+        let allocated = file_nr.first().cloned().unwrap_or(0);
+        let unused = file_nr.get(1).cloned().unwrap_or(0);
+        let fd_max = file_nr.get(2).cloned().unwrap_or(0);
+
+        let mut parse_next = || {
+            lines
+                .next()
+                .and_then(|v| u64::from_str(v.trim()).ok())
+                .unwrap_or(0)
+        };
+        let conntrack_used = parse_next();
+        let conntrack_max = parse_next();
+
+        // Newer kernels report LONG_MAX as the file descriptor limit, floats keep cluster
+        // totals of such limits from overflowing
+        Metrics::Limits(LimitsMetrics {
+            fd_used: allocated.saturating_sub(unused) as f64,
+            fd_max: fd_max as f64,
+            conntrack_used: conntrack_used as f64,
+            conntrack_max: conntrack_max as f64,
+        })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Limits(LimitsMetrics::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_process_data() {
+        let raw_data = "11264\t0\t9223372036854775807\n3081\n262144\n";
+        let metrics = LimitsMetrics {
+            fd_used: 11264.,
+            fd_max: i64::MAX as f64,
+            conntrack_used: 3081.,
+            conntrack_max: 262144.,
+        };
+        assert_parse(raw_data, metrics);
+
+        let raw_data = "2080\t0\t1620000\n";
+        let metrics = LimitsMetrics {
+            fd_used: 2080.,
+            fd_max: 1620000.,
+            conntrack_used: 0.,
+            conntrack_max: 0.,
+        };
+        assert_parse(raw_data, metrics);
+        assert_parse("", LimitsMetrics::default());
+    }
+
+    fn assert_parse(raw_data: &str, expected_metrics: LimitsMetrics) {
+        let mut metric_plugin = LimitsMetricPlugin::new();
+        let now = SystemTime::now();
+        let metrics = metric_plugin.process_data(raw_data, &now);
+
+        assert_eq!(metrics, Metrics::Limits(expected_metrics));
+    }
+}
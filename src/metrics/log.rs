@@ -0,0 +1,279 @@
+use super::{shell_quote, MetricPlugin, Metrics};
+use crate::config::LogPatternConfig;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+const SEPARATOR: &str = "--";
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct LogMetrics {
+    rates: BTreeMap<String, f64>,
+}
+
+impl Add for LogMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut rates = self.rates;
+        for (name, rate) in other.rates {
+            *rates.entry(name).or_insert(0.0) += rate;
+        }
+        Self { rates }
+    }
+}
+
+impl LogMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        let rates = self
+            .rates
+            .into_iter()
+            .map(|(name, rate)| (name, rate / divisor))
+            .collect();
+        Self { rates }
+    }
+}
+
+/// Where the plugin left off reading a log on the previous poll
+#[derive(Debug, PartialEq)]
+enum Position {
+    Unknown,
+    File { inode: String, offset: u64 },
+    Journal { cursor: String },
+}
+
+struct LogWatch {
+    config: LogPatternConfig,
+    position: Position,
+}
+
+impl LogWatch {
+    /// Builds a script that prints the new position followed by the number of matching lines
+    fn query(&self) -> String {
+        let pattern = shell_quote(&self.config.pattern);
+        let count = "RE=\"$pattern\" awk '/^-- cursor: / {c = substr($0, 12); next} \
+                     $0 ~ ENVIRON[\"RE\"] {n++} END {print n+0; print c}'";
+
+        match (&self.config.file, &self.position) {
+            (Some(file), Position::File { inode, offset }) => format!(
+                "(pattern={pattern}; set -- $(stat -Lc '%i %s' {file} 2>/dev/null || echo 0 0); \
+                 echo \"$1 $2\"; o={offset}; \
+                 [ \"$1\" = {inode} ] && [ \"$2\" -ge \"$o\" ] || o=0; \
+                 tail -c +$((o+1)) {file} 2>/dev/null | head -c $(($2-o)) | {count})",
+                pattern = pattern,
+                file = shell_quote(file),
+                offset = offset,
+                inode = shell_quote(inode),
+                count = count,
+            ),
+            (Some(file), _) => format!(
+                "(stat -Lc '%i %s' {} 2>/dev/null || echo 0 0)",
+                shell_quote(file)
+            ),
+            (None, position) => {
+                let unit = match &self.config.unit {
+                    Some(unit) => format!(" -u {}", shell_quote(unit)),
+                    None => "".to_string(),
+                };
+                let range = match position {
+                    Position::Journal { cursor } => {
+                        format!(" --after-cursor={}", shell_quote(cursor))
+                    }
+                    _ => " -n 1".to_string(),
+                };
+                format!(
+                    "(pattern={}; journalctl -q --no-pager -o cat --show-cursor{}{} \
+                     2>/dev/null | {})",
+                    pattern, unit, range, count
+                )
+            }
+        }
+    }
+
+    /// Moves the position forward and returns the number of new matching lines
+    fn advance(&mut self, raw_data: &str) -> u64 {
+        let mut lines = raw_data.lines();
+        let first = lines.next().unwrap_or("");
+        let second = lines.next().unwrap_or("");
+        let first_poll = self.position == Position::Unknown;
+
+        let matches = if self.config.file.is_some() {
+            let mut parts = first.split_whitespace();
+            let inode = parts.next().unwrap_or("0").to_string();
+            let offset = parts
+                .next()
+                .and_then(|v| u64::from_str(v).ok())
+                .unwrap_or(0);
+            self.position = Position::File { inode, offset };
+            second.trim()
+        } else {
+            let cursor = second.trim();
+            if !cursor.is_empty() {
+                self.position = Position::Journal {
+                    cursor: cursor.to_string(),
+                };
+            }
+            first.trim()
+        };
+
+        if first_poll {
+            // Everything that was logged before Hearth started is not a new match
+            return 0;
+        }
+        u64::from_str(matches).unwrap_or(0)
+    }
+}
+
+/// Reports how often lines matching a pattern are appended to log files or the journal
+pub struct LogMetricPlugin {
+    watches: Vec<LogWatch>,
+    command: String,
+    previous_time: Option<SystemTime>,
+}
+
+impl LogMetricPlugin {
+    pub fn new(configs: &[LogPatternConfig]) -> Self {
+        let watches = configs
+            .iter()
+            .map(|config| LogWatch {
+                config: config.clone(),
+                position: Position::Unknown,
+            })
+            .collect();
+        let mut plugin = Self {
+            watches,
+            command: "".to_string(),
+            previous_time: None,
+        };
+        plugin.update_command();
+        plugin
+    }
+
+    fn update_command(&mut self) {
+        self.command = self
+            .watches
+            .iter()
+            .map(|watch| watch.query())
+            .collect::<Vec<String>>()
+            .join(&format!("; echo '{}'; ", SEPARATOR));
+    }
+}
+
+impl MetricPlugin for LogMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let time_elapsed = self
+            .previous_time
+            .and_then(|previous| timestamp.duration_since(previous).ok())
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or(0.0);
+
+        let separator = format!("{}\n", SEPARATOR);
+        let mut outputs = raw_data.split(&separator);
+        let rates = self
+            .watches
+            .iter_mut()
+            .map(|watch| {
+                let matches = watch.advance(outputs.next().unwrap_or(""));
+                let rate = if time_elapsed > 0.0 {
+                    matches as f64 / time_elapsed
+                } else {
+                    0.0
+                };
+                (watch.config.name.clone(), rate)
+            })
+            .collect();
+
+        self.previous_time = Some(*timestamp);
+        self.update_command();
+
+        Metrics::Log(LogMetrics { rates })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Log(LogMetrics::default())
+    }
+
+    /// The query continues where the previous one left off
+    fn streamable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 = "1837 52000\n--\n0\ns=4c1f;i=1a2b\n";
+        let raw_data_2 = "1837 54000\n6\n--\n3\ns=4c1f;i=1a3c\n";
+        let rates = [("app".to_string(), 3.), ("kernel".to_string(), 1.5)];
+        assert_parse(raw_data_1, raw_data_2, &rates);
+
+        let rates = [("app".to_string(), 0.), ("kernel".to_string(), 0.)];
+        assert_parse("", "", &rates);
+    }
+
+    #[test]
+    fn test_position_tracking() {
+        let mut metric_plugin = LogMetricPlugin::new(&configs());
+        let now = SystemTime::now();
+        metric_plugin.process_data("1837 52000\n--\n0\ns=4c1f;i=1a2b\n", &now);
+
+        assert_eq!(
+            metric_plugin.watches[0].position,
+            Position::File {
+                inode: "1837".to_string(),
+                offset: 52000
+            }
+        );
+        assert_eq!(
+            metric_plugin.watches[1].position,
+            Position::Journal {
+                cursor: "s=4c1f;i=1a2b".to_string()
+            }
+        );
+        assert!(metric_plugin.get_query().contains("o=52000;"));
+        assert!(metric_plugin
+            .get_query()
+            .contains("--after-cursor='s=4c1f;i=1a2b'"));
+    }
+
+    fn configs() -> Vec<LogPatternConfig> {
+        vec![
+            LogPatternConfig {
+                name: "app".to_string(),
+                pattern: "ERROR".to_string(),
+                file: Some("/var/log/app.log".to_string()),
+                unit: None,
+            },
+            LogPatternConfig {
+                name: "kernel".to_string(),
+                pattern: "Out of memory".to_string(),
+                file: None,
+                unit: None,
+            },
+        ]
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, rates: &[(String, f64)]) {
+        let mut metric_plugin = LogMetricPlugin::new(&configs());
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416626, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        let expected_metrics = Metrics::Log(LogMetrics {
+            rates: rates.iter().cloned().collect(),
+        });
+
+        assert_eq!(metrics, expected_metrics);
+    }
+}
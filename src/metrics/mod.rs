@@ -1,23 +1,86 @@
 pub mod aggregator;
+mod cgroup;
+mod clock;
+mod collection;
 mod cpu;
+mod custom;
 mod disk;
+pub mod event;
+mod haproxy;
 pub mod hub;
+mod kernel;
 mod la;
+mod limits;
+mod log;
 mod metric_buffer;
 mod network;
+mod nginx;
+mod ports;
+mod postgres;
+mod process;
+mod raid;
 mod ram;
+mod redis;
 mod space;
+mod systemd;
+mod updates;
 
-use std::time::SystemTime;
+use crate::config::{LocalConfig, ServerConfig, TransportConfig};
+use event::EventKind;
+use std::time::{Duration, SystemTime};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Metrics {
+    Cgroup(cgroup::CgroupMetrics),
+    Clock(clock::ClockMetrics),
     Cpu(cpu::CpuMetrics),
+    Custom(custom::CustomMetrics),
     Disk(disk::DiskMetrics),
+    Haproxy(haproxy::HaproxyMetrics),
+    Kernel(kernel::KernelMetrics),
     La(la::LaMetrics),
+    Limits(limits::LimitsMetrics),
+    Log(log::LogMetrics),
     Net(network::NetMetrics),
+    Nginx(nginx::NginxMetrics),
+    Ports(ports::PortMetrics),
+    Postgres(postgres::PostgresMetrics),
+    Process(process::ProcessMetrics),
+    Raid(raid::RaidMetrics),
     Ram(ram::RamMetrics),
+    Redis(redis::RedisMetrics),
     Space(space::SpaceMetrics),
+    Systemd(systemd::SystemdMetrics),
+    Updates(updates::UpdateMetrics),
+}
+
+impl Metrics {
+    /// Name of the plugin the metrics come from, used when reporting its failures
+    pub fn name(&self) -> &str {
+        match self {
+            Metrics::Cgroup(_) => "cgroups",
+            Metrics::Clock(_) => "clock",
+            Metrics::Cpu(_) => "cpu",
+            Metrics::Custom(m) => m.name(),
+            Metrics::Disk(_) => "disk",
+            Metrics::Haproxy(_) => "haproxy",
+            Metrics::Kernel(_) => "kernel",
+            Metrics::La(_) => "la",
+            Metrics::Limits(_) => "limits",
+            Metrics::Log(_) => "logs",
+            Metrics::Net(_) => "network",
+            Metrics::Nginx(_) => "nginx",
+            Metrics::Ports(_) => "ports",
+            Metrics::Postgres(_) => "postgres",
+            Metrics::Process(_) => "processes",
+            Metrics::Raid(_) => "raid",
+            Metrics::Ram(_) => "ram",
+            Metrics::Redis(_) => "redis",
+            Metrics::Space(_) => "space",
+            Metrics::Systemd(_) => "systemd",
+            Metrics::Updates(_) => "updates",
+        }
+    }
 }
 
 /// Interface for Metric Plugins that possess the knowledge of retrieving raw metric data and
@@ -31,22 +94,125 @@ pub trait MetricPlugin: Send + 'static {
 
     /// Returns a HashMap with keys and empty values
     fn empty_metrics(&self) -> Metrics;
+
+    /// Receives the time the query was sent at and the round-trip time to the server before
+    /// the raw data is processed
+    fn request_timing(&mut self, _sent: SystemTime, _round_trip: Duration) {}
+
+    /// How often the query should be run, expensive queries are left out of most batches and
+    /// their last metrics are reported in between
+    fn interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// Files whose contents, one after the other, are all the query needs. Transports that can
+    /// read files directly do so instead of running the query, files that don't exist are left out.
+    fn files(&self) -> Vec<&str> {
+        vec![]
+    }
+
+    /// Whether the query can be left running in a loop on the server. Queries that change
+    /// between polls or depend on when they were sent have to be run one by one.
+    fn streamable(&self) -> bool {
+        true
+    }
+
+    /// Queries that take longer than a tick run on a channel of their own instead of holding up
+    /// the batch, and are given up after this long. Their last metrics are reported meanwhile.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Forgets previous samples, called when the server has rebooted and counters start over
+    fn reset(&mut self) {}
+
+    /// Returns the events noticed while processing data since the last call
+    fn take_events(&mut self) -> Vec<EventKind> {
+        vec![]
+    }
 }
 
-/// Creates all possible metric plugins and returns them as a HashMap
-fn metric_plugin_factory(
-    disk: &str,
-    filesystem: &str,
-    network_interface: &str,
-) -> Vec<Box<dyn MetricPlugin>> {
-    let metric_plugins: Vec<Box<dyn MetricPlugin>> = vec![
+/// Creates all metric plugins that are enabled for the given server
+fn metric_plugin_factory(server_config: &ServerConfig) -> Vec<Box<dyn MetricPlugin>> {
+    // The clock is read first so that slow queries don't delay it
+    let mut metric_plugins: Vec<Box<dyn MetricPlugin>> = vec![
+        Box::new(clock::ClockMetricPlugin::new()),
         Box::new(cpu::CpuMetricPlugin::new()),
         Box::new(ram::RamMetricPlugin::new()),
         Box::new(la::LoadAverageMetricPlugin::new()),
-        Box::new(disk::DiskMetricPlugin::new(disk)),
-        Box::new(network::NetworkMetricPlugin::new(network_interface)),
-        Box::new(space::SpaceMetricPlugin::new(filesystem)),
+        Box::new(disk::DiskMetricPlugin::new(&server_config.disk)),
+        Box::new(network::NetworkMetricPlugin::new(&server_config.network_interface)),
+        Box::new(space::SpaceMetricPlugin::new(&server_config.filesystem)),
+        Box::new(kernel::KernelMetricPlugin::new()),
+        Box::new(limits::LimitsMetricPlugin::new()),
+        Box::new(raid::RaidMetricPlugin::new()),
+        Box::new(ports::PortMetricPlugin::new()),
     ];
 
+    if let Some(systemd_config) = &server_config.systemd {
+        // systemctl can't be pointed at the host's mounts, it would only see the container
+        if let TransportConfig::Local(LocalConfig { root: Some(root) }) = &server_config.transport {
+            ::log::warn!(
+                "[{}] systemd units can't be watched below {}, leaving them out",
+                server_config.hostname,
+                root
+            );
+        } else {
+            metric_plugins.push(Box::new(systemd::SystemdMetricPlugin::new(systemd_config)));
+        }
+    }
+
+    if let Some(cgroup_config) = &server_config.cgroups {
+        metric_plugins.push(Box::new(cgroup::CgroupMetricPlugin::new(cgroup_config)));
+    }
+
+    if !server_config.log_patterns.is_empty() {
+        metric_plugins.push(Box::new(log::LogMetricPlugin::new(&server_config.log_patterns)));
+    }
+
+    if !server_config.watch_processes.is_empty() {
+        metric_plugins.push(Box::new(process::ProcessMetricPlugin::new(
+            &server_config.watch_processes,
+        )));
+    }
+
+    if let Some(nginx_config) = &server_config.nginx {
+        metric_plugins.push(Box::new(nginx::NginxMetricPlugin::new(nginx_config)));
+    }
+
+    if let Some(redis_config) = &server_config.redis {
+        metric_plugins.push(Box::new(redis::RedisMetricPlugin::new(redis_config)));
+    }
+
+    if let Some(postgres_config) = &server_config.postgres {
+        metric_plugins.push(Box::new(postgres::PostgresMetricPlugin::new(postgres_config)));
+    }
+
+    if let Some(haproxy_config) = &server_config.haproxy {
+        metric_plugins.push(Box::new(haproxy::HaproxyMetricPlugin::new(haproxy_config)));
+    }
+
+    if let Some(updates_config) = &server_config.updates {
+        metric_plugins.push(Box::new(updates::UpdateMetricPlugin::new(updates_config)));
+    }
+
+    server_config.custom_metrics.iter().for_each(|custom_config| {
+        metric_plugins.push(Box::new(custom::CustomMetricPlugin::new(custom_config)));
+    });
+
     metric_plugins
 }
+
+/// Quotes a value so that it can be safely embedded into a shell command
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Per second rate of an ever-growing counter, None when it went backwards because it has been
+/// reset, the sample then only serves as a new baseline
+fn counter_rate(current: u64, previous: u64, seconds: f64) -> Option<f64> {
+    match current.checked_sub(previous) {
+        Some(difference) if seconds > 0.0 => Some(difference as f64 / seconds),
+        _ => None,
+    }
+}
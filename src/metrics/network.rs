@@ -1,4 +1,4 @@
-use super::{MetricPlugin, Metrics};
+use super::{counter_rate, MetricPlugin, Metrics};
 use derive_more::Add;
 use serde_derive::Serialize;
 use std::str::FromStr;
@@ -22,16 +22,22 @@ impl NetMetrics {
 pub struct NetworkMetricPlugin {
     network: Network,
     command: String,
+    paths: Vec<String>,
 }
 
 impl NetworkMetricPlugin {
     pub fn new(interface: &str) -> Self {
         let network = Network::default();
-        let command = format!(
-            "cat /sys/class/net/{0}/statistics/rx_bytes /sys/class/net/{0}/statistics/tx_bytes",
-            interface
-        );
-        Self { network, command }
+        let paths = vec![
+            format!("/sys/class/net/{}/statistics/rx_bytes", interface),
+            format!("/sys/class/net/{}/statistics/tx_bytes", interface),
+        ];
+        let command = format!("cat {}", paths.join(" "));
+        Self {
+            network,
+            command,
+            paths,
+        }
     }
 }
 
@@ -40,6 +46,10 @@ impl MetricPlugin for NetworkMetricPlugin {
         &self.command
     }
 
+    fn files(&self) -> Vec<&str> {
+        self.paths.iter().map(|path| path.as_str()).collect()
+    }
+
     fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
         let network_stats = NetworkStats::from_string(&raw_data, timestamp);
 
@@ -54,6 +64,10 @@ impl MetricPlugin for NetworkMetricPlugin {
     fn empty_metrics(&self) -> Metrics {
         Metrics::Net(NetMetrics::default())
     }
+
+    fn reset(&mut self) {
+        self.network.reset();
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -113,43 +127,43 @@ impl NetworkStats {
 
 #[derive(Default)]
 pub struct Network {
-    previous_network_stats: NetworkStats,
+    previous_network_stats: Option<NetworkStats>,
     down_bandwidth: f64,
     up_bandwidth: f64,
 }
 
 impl Network {
-    pub fn push(&mut self, network_stats: NetworkStats) {
-        macro_rules! diff {
-            ($this:expr, $that:expr) => {
-                if $this > $that {
-                    $this - $that
-                } else {
-                    $that - $this
-                }
-            };
-        }
+    pub fn reset(&mut self) {
+        self.previous_network_stats = None;
+    }
 
+    pub fn push(&mut self, network_stats: NetworkStats) {
+        // The first sample only serves as a baseline
+        let previous = match self.previous_network_stats.replace(network_stats) {
+            Some(previous) => previous,
+            None => {
+                self.down_bandwidth = 0.0;
+                self.up_bandwidth = 0.0;
+                return;
+            }
+        };
         let time_elapsed = network_stats
             .current_time()
-            .duration_since(self.previous_network_stats.current_time())
-            .expect("There is a bug in elapsed time calculation");
-        let time_elapsed =
-            time_elapsed.as_secs() as f64 + time_elapsed.subsec_millis() as f64 / 1000.0;
-
-        let rx_bytes = diff!(
-            network_stats.rx_bytes(),
-            self.previous_network_stats.rx_bytes()
-        ) as f64;
-
-        let tx_bytes = diff!(
-            network_stats.tx_bytes(),
-            self.previous_network_stats.tx_bytes()
-        ) as f64;
-
-        self.down_bandwidth = rx_bytes / time_elapsed;
-        self.up_bandwidth = tx_bytes / time_elapsed;
-        self.previous_network_stats = network_stats;
+            .duration_since(previous.current_time())
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        // The bandwidth from before a counter reset is reported again
+        if let Some(rate) =
+            counter_rate(network_stats.rx_bytes(), previous.rx_bytes(), time_elapsed)
+        {
+            self.down_bandwidth = rate;
+        }
+        if let Some(rate) =
+            counter_rate(network_stats.tx_bytes(), previous.tx_bytes(), time_elapsed)
+        {
+            self.up_bandwidth = rate;
+        }
     }
 
     pub fn down_bandwidth(&self) -> f64 {
@@ -177,6 +191,19 @@ mod test {
         assert_parse("", "", 0., 0.);
     }
 
+    #[test]
+    fn test_counter_reset() {
+        let mut metric_plugin = NetworkMetricPlugin::new("eth0");
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data("33597756273\n11137558032", &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data("33597768357\n11137566224", &now);
+        let now = UNIX_EPOCH + Duration::new(1531416626, 0);
+
+        // The sample after the reset reports the bandwidth of the one before
+        assert_eq!(metric_plugin.process_data("12084\n8192", &now), metrics);
+    }
+
     fn assert_parse(raw_data_1: &str, raw_data_2: &str, down_bandwidth: f64, up_bandwidth: f64) {
         let mut metric_plugin = NetworkMetricPlugin::new("eth0");
         let now = UNIX_EPOCH + Duration::new(1531416624, 0);
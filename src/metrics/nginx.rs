@@ -0,0 +1,144 @@
+use super::{counter_rate, shell_quote, MetricPlugin, Metrics};
+use crate::config::NginxConfig;
+use derive_more::Add;
+use serde_derive::Serialize;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Add)]
+pub struct NginxMetrics {
+    active_connections: f64,
+    reading: f64,
+    writing: f64,
+    waiting: f64,
+    requests_per_second: f64,
+}
+
+impl NginxMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            active_connections: self.active_connections / divisor,
+            reading: self.reading / divisor,
+            writing: self.writing / divisor,
+            waiting: self.waiting / divisor,
+            requests_per_second: self.requests_per_second / divisor,
+        }
+    }
+}
+
+/// Reads the connection and request counters of the nginx stub_status page
+pub struct NginxMetricPlugin {
+    command: String,
+    previous: Option<(u64, SystemTime)>,
+    /// Reported for the previous sample, its rates are reported again after a counter reset
+    last_metrics: NginxMetrics,
+}
+
+impl NginxMetricPlugin {
+    pub fn new(config: &NginxConfig) -> Self {
+        let command = format!(
+            "curl -sf --max-time 1 {} 2>/dev/null",
+            shell_quote(&config.status_url)
+        );
+        Self {
+            command,
+            previous: None,
+            last_metrics: NginxMetrics::default(),
+        }
+    }
+}
+
+impl MetricPlugin for NginxMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let mut metrics = NginxMetrics::default();
+        let mut requests = None;
+
+        for line in raw_data.lines() {
+            let numbers: Vec<u64> = line
+                .split_whitespace()
+                .filter_map(|v| u64::from_str(v).ok())
+                .collect();
+            if line.starts_with("Active connections:") {
+                metrics.active_connections = numbers.first().cloned().unwrap_or(0) as f64;
+            } else if line.starts_with("Reading:") && numbers.len() == 3 {
+                metrics.reading = numbers[0] as f64;
+                metrics.writing = numbers[1] as f64;
+                metrics.waiting = numbers[2] as f64;
+            } else if numbers.len() == 3 {
+                // The line below "server accepts handled requests"
+                requests = Some(numbers[2]);
+            }
+        }
+
+        if let Some(requests) = requests {
+            if let Some((previous, previous_time)) = self.previous {
+                let time_elapsed = timestamp
+                    .duration_since(previous_time)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                metrics.requests_per_second = counter_rate(requests, previous, time_elapsed)
+                    .unwrap_or(self.last_metrics.requests_per_second);
+            }
+            self.previous = Some((requests, *timestamp));
+        }
+
+        self.last_metrics = metrics.clone();
+
+        Metrics::Nginx(metrics)
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Nginx(NginxMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+        self.last_metrics = NginxMetrics::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 = "Active connections: 291 
+server accepts handled requests
+ 16630948 16630948 31070465 
+Reading: 6 Writing: 179 Waiting: 106 
+";
+        let raw_data_2 = "Active connections: 280 
+server accepts handled requests
+ 16630990 16630990 31070565 
+Reading: 4 Writing: 170 Waiting: 106 
+";
+        let metrics = NginxMetrics {
+            active_connections: 280.,
+            reading: 4.,
+            writing: 170.,
+            waiting: 106.,
+            requests_per_second: 50.,
+        };
+        assert_parse(raw_data_1, raw_data_2, metrics);
+        assert_parse("", "", NginxMetrics::default());
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: NginxMetrics) {
+        let config = NginxConfig {
+            status_url: "http://127.0.0.1/nginx_status".to_string(),
+        };
+        let mut metric_plugin = NginxMetricPlugin::new(&config);
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416626, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Nginx(expected_metrics));
+    }
+}
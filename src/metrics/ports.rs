@@ -0,0 +1,193 @@
+use super::{event::EventKind, MetricPlugin, Metrics};
+use serde_derive::Serialize;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct PortMetrics {
+    sockets: Vec<ListeningSocket>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Serialize)]
+pub struct ListeningSocket {
+    protocol: String,
+    port: u16,
+    address: String,
+    /// Only known for processes that belong to the SSH user, unless it is root
+    process: String,
+}
+
+impl ListeningSocket {
+    /// Identifies a socket, the process isn't part of it as it may not always be visible
+    fn key(&self) -> (&str, &str, u16) {
+        (&self.protocol, &self.address, self.port)
+    }
+
+    fn is_in(&self, sockets: &[ListeningSocket]) -> bool {
+        sockets.iter().any(|socket| socket.key() == self.key())
+    }
+}
+
+/// Lists every socket once, no matter on how many nodes or in how many samples it was seen
+impl Add for PortMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut sockets = self.sockets;
+        for socket in other.sockets {
+            if !socket.is_in(&sockets) {
+                sockets.push(socket);
+            }
+        }
+        sockets.sort();
+        Self { sockets }
+    }
+}
+
+impl PortMetrics {
+    pub fn divide(self, _: f64) -> Self {
+        self
+    }
+}
+
+/// Keeps an inventory of listening TCP and UDP sockets and notices when they come and go
+pub struct PortMetricPlugin {
+    previous: Option<Vec<ListeningSocket>>,
+    events: Vec<EventKind>,
+}
+
+impl PortMetricPlugin {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            events: vec![],
+        }
+    }
+}
+
+impl MetricPlugin for PortMetricPlugin {
+    fn get_query(&self) -> &'static str {
+        // The header line doesn't parse as a socket and is left out
+        "ss -ltnup 2>/dev/null"
+    }
+
+    fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
+        let mut sockets: Vec<ListeningSocket> = raw_data
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let (address, port) = fields.get(4)?.rsplit_once(':')?;
+                // Processes look like users:(("sshd",pid=812,fd=3),("sshd",pid=813,fd=3))
+                let process = fields
+                    .get(6)
+                    .and_then(|users| users.split('"').nth(1))
+                    .unwrap_or("");
+                Some(ListeningSocket {
+                    protocol: fields[0].to_string(),
+                    port: u16::from_str(port).ok()?,
+                    address: address.to_string(),
+                    process: process.to_string(),
+                })
+            })
+            .collect();
+        sockets.sort();
+        sockets.dedup_by(|a, b| a.key() == b.key());
+
+        // A host always listens on something, no sockets at all means ss didn't tell us
+        if sockets.is_empty() {
+            return Metrics::Ports(PortMetrics { sockets });
+        }
+        if let Some(previous) = &self.previous {
+            for socket in sockets.iter().filter(|s| !s.is_in(previous)) {
+                self.events.push(EventKind::PortOpened(socket.clone()));
+            }
+            for socket in previous.iter().filter(|s| !s.is_in(&sockets)) {
+                self.events.push(EventKind::PortClosed(socket.clone()));
+            }
+        }
+        self.previous = Some(sockets.clone());
+
+        Metrics::Ports(PortMetrics { sockets })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Ports(PortMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    fn take_events(&mut self) -> Vec<EventKind> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_process_data() {
+        let raw_data = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      128          0.0.0.0:22        0.0.0.0:*    users:((\"sshd\",pid=812,fd=3))
+tcp   LISTEN 0      128             [::]:22          [::]:*    users:((\"sshd\",pid=812,fd=4))
+tcp   LISTEN 0      511        127.0.0.1:6379      0.0.0.0:*
+udp   UNCONN 0      0      0.0.0.0%eth0:68         0.0.0.0:*
+";
+        let metrics = PortMetrics {
+            sockets: vec![
+                socket("tcp", 22, "0.0.0.0", "sshd"),
+                socket("tcp", 22, "[::]", "sshd"),
+                socket("tcp", 6379, "127.0.0.1", ""),
+                socket("udp", 68, "0.0.0.0%eth0", ""),
+            ],
+        };
+        let mut metric_plugin = PortMetricPlugin::new();
+        let now = SystemTime::now();
+        let metrics_1 = metric_plugin.process_data(raw_data, &now);
+        assert_eq!(metrics_1, Metrics::Ports(metrics));
+        assert_eq!(metric_plugin.take_events(), vec![]);
+
+        let metrics_2 = metric_plugin.process_data("", &now);
+        assert_eq!(metrics_2, Metrics::Ports(PortMetrics::default()));
+        assert_eq!(metric_plugin.take_events(), vec![]);
+    }
+
+    #[test]
+    fn test_events() {
+        let raw_data_1 = "\
+tcp   LISTEN 0      128          0.0.0.0:22        0.0.0.0:*    users:((\"sshd\",pid=812,fd=3))
+tcp   LISTEN 0      511        127.0.0.1:6379      0.0.0.0:*
+";
+        // The process of sshd isn't visible anymore, the socket is still the same
+        let raw_data_2 = "\
+tcp   LISTEN 0      128          0.0.0.0:22        0.0.0.0:*
+tcp   LISTEN 0      4096         0.0.0.0:8080      0.0.0.0:*    users:((\"python3\",pid=9000,fd=5))
+";
+        let mut metric_plugin = PortMetricPlugin::new();
+        let now = SystemTime::now();
+        metric_plugin.process_data(raw_data_1, &now);
+        metric_plugin.process_data("", &now);
+        metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(
+            metric_plugin.take_events(),
+            vec![
+                EventKind::PortOpened(socket("tcp", 8080, "0.0.0.0", "python3")),
+                EventKind::PortClosed(socket("tcp", 6379, "127.0.0.1", "")),
+            ]
+        );
+    }
+
+    fn socket(protocol: &str, port: u16, address: &str, process: &str) -> ListeningSocket {
+        ListeningSocket {
+            protocol: protocol.to_string(),
+            port,
+            address: address.to_string(),
+            process: process.to_string(),
+        }
+    }
+}
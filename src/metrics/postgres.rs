@@ -0,0 +1,189 @@
+use super::{counter_rate, shell_quote, MetricPlugin, Metrics};
+use crate::config::PostgresConfig;
+use serde_derive::Serialize;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+const QUERY: &str = "SELECT \
+    (SELECT count(*) FROM pg_stat_activity WHERE backend_type = 'client backend'), \
+    (SELECT count(*) FROM pg_stat_activity WHERE state = 'active'), \
+    sum(xact_commit + xact_rollback), sum(blks_hit), sum(blks_read), \
+    CASE WHEN pg_is_in_recovery() \
+    THEN COALESCE(EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()), 0) \
+    ELSE 0 END \
+    FROM pg_stat_database";
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct PostgresMetrics {
+    connections: f64,
+    active_connections: f64,
+    transactions_per_second: f64,
+    /// Blocks found in the buffer cache and blocks read from disk per second
+    blocks_hit_per_second: f64,
+    blocks_read_per_second: f64,
+    /// Seconds since the last replayed transaction on a standby
+    replication_lag: f64,
+}
+
+/// Replication lags don't add up, the largest one is kept
+impl Add for PostgresMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            connections: self.connections + other.connections,
+            active_connections: self.active_connections + other.active_connections,
+            transactions_per_second: self.transactions_per_second + other.transactions_per_second,
+            blocks_hit_per_second: self.blocks_hit_per_second + other.blocks_hit_per_second,
+            blocks_read_per_second: self.blocks_read_per_second + other.blocks_read_per_second,
+            replication_lag: self.replication_lag.max(other.replication_lag),
+        }
+    }
+}
+
+impl PostgresMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            connections: self.connections / divisor,
+            active_connections: self.active_connections / divisor,
+            transactions_per_second: self.transactions_per_second / divisor,
+            blocks_hit_per_second: self.blocks_hit_per_second / divisor,
+            blocks_read_per_second: self.blocks_read_per_second / divisor,
+            replication_lag: self.replication_lag,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PostgresCounters {
+    transactions: u64,
+    blocks_hit: u64,
+    blocks_read: u64,
+}
+
+/// Queries connections, throughput, cache hits and replication lag from the statistics views
+pub struct PostgresMetricPlugin {
+    command: String,
+    previous: Option<(PostgresCounters, SystemTime)>,
+    /// Reported for the previous sample, its rates are reported again after a counter reset
+    last_metrics: PostgresMetrics,
+}
+
+impl PostgresMetricPlugin {
+    pub fn new(config: &PostgresConfig) -> Self {
+        let sudo = match &config.sudo_user {
+            Some(sudo_user) => format!("sudo -n -u {} ", shell_quote(sudo_user)),
+            None => "".to_string(),
+        };
+        let user = match &config.user {
+            Some(user) => format!(" -U {}", shell_quote(user)),
+            None => "".to_string(),
+        };
+        let command = format!(
+            "{}psql -X -A -t -F ' '{} -d {} -c {} 2>/dev/null",
+            sudo,
+            user,
+            shell_quote(&config.database),
+            shell_quote(QUERY)
+        );
+        Self {
+            command,
+            previous: None,
+            last_metrics: PostgresMetrics::default(),
+        }
+    }
+}
+
+impl MetricPlugin for PostgresMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let values: Vec<f64> = raw_data
+            .split_whitespace()
+            .map(|v| f64::from_str(v).unwrap_or(0.0))
+            .collect();
+        if values.len() < 6 {
+            return self.empty_metrics();
+        }
+
+        let counters = PostgresCounters {
+            transactions: values[2] as u64,
+            blocks_hit: values[3] as u64,
+            blocks_read: values[4] as u64,
+        };
+        let mut metrics = PostgresMetrics {
+            connections: values[0],
+            active_connections: values[1],
+            replication_lag: values[5],
+            ..PostgresMetrics::default()
+        };
+        if let Some((previous, previous_time)) = self.previous {
+            let time_elapsed = timestamp
+                .duration_since(previous_time)
+                .unwrap_or_default()
+                .as_secs_f64();
+            metrics.transactions_per_second =
+                counter_rate(counters.transactions, previous.transactions, time_elapsed)
+                    .unwrap_or(self.last_metrics.transactions_per_second);
+            metrics.blocks_hit_per_second =
+                counter_rate(counters.blocks_hit, previous.blocks_hit, time_elapsed)
+                    .unwrap_or(self.last_metrics.blocks_hit_per_second);
+            metrics.blocks_read_per_second =
+                counter_rate(counters.blocks_read, previous.blocks_read, time_elapsed)
+                    .unwrap_or(self.last_metrics.blocks_read_per_second);
+        }
+        self.previous = Some((counters, *timestamp));
+        self.last_metrics = metrics.clone();
+
+        Metrics::Postgres(metrics)
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Postgres(PostgresMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+        self.last_metrics = PostgresMetrics::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 = "42 3 1200000 98000000 40000 0\n";
+        let raw_data_2 = "40 5 1200300 98004000 40100 0.25\n";
+        let metrics = PostgresMetrics {
+            connections: 40.,
+            active_connections: 5.,
+            transactions_per_second: 150.,
+            blocks_hit_per_second: 2000.,
+            blocks_read_per_second: 50.,
+            replication_lag: 0.25,
+        };
+        assert_parse(raw_data_1, raw_data_2, metrics);
+        assert_parse("", "", PostgresMetrics::default());
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: PostgresMetrics) {
+        let config = PostgresConfig {
+            database: "postgres".to_string(),
+            user: None,
+            sudo_user: Some("postgres".to_string()),
+        };
+        let mut metric_plugin = PostgresMetricPlugin::new(&config);
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416626, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Postgres(expected_metrics));
+    }
+}
@@ -0,0 +1,289 @@
+use super::{MetricPlugin, Metrics};
+use crate::config::WatchProcessConfig;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+const SEPARATOR: &str = "--";
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct ProcessMetrics {
+    groups: Vec<ProcessGroup>,
+}
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct ProcessGroup {
+    name: String,
+    /// Number of matching processes, the node is unhealthy when there are none
+    count: f64,
+    /// Summed CPU usage in percent of a single core
+    cpu_usage: f64,
+    /// Summed resident set size in bytes
+    rss: f64,
+}
+
+/// Groups are matched by name, so that rollups don't list the same process several times
+impl Add for ProcessMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut groups = self.groups;
+        for group in other.groups {
+            match groups.iter_mut().find(|g| g.name == group.name) {
+                Some(existing) => {
+                    existing.count += group.count;
+                    existing.cpu_usage += group.cpu_usage;
+                    existing.rss += group.rss;
+                }
+                None => groups.push(group),
+            }
+        }
+        Self { groups }
+    }
+}
+
+impl ProcessMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        let groups = self
+            .groups
+            .into_iter()
+            .map(|group| ProcessGroup {
+                name: group.name,
+                count: group.count / divisor,
+                cpu_usage: group.cpu_usage / divisor,
+                rss: group.rss / divisor,
+            })
+            .collect();
+        Self { groups }
+    }
+}
+
+/// Processes are identified by pid and start time, so that reused pids are not mixed up
+type ProcessId = (u64, u64);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ProcessStats {
+    /// Time spent in user and kernel mode in clock ticks
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Reports the number, CPU and memory usage of processes matching the configured patterns
+pub struct ProcessMetricPlugin {
+    names: Vec<String>,
+    command: String,
+    previous: Option<(Vec<HashMap<ProcessId, ProcessStats>>, SystemTime)>,
+}
+
+impl ProcessMetricPlugin {
+    pub fn new(configs: &[WatchProcessConfig]) -> Self {
+        let watches: Vec<String> = configs
+            .iter()
+            .map(|config| {
+                let pattern = config.pattern.as_ref().unwrap_or(&config.name);
+                let full = if config.full_command { "-f " } else { "" };
+                // The pattern is passed as octal escapes, otherwise pgrep -f would match the
+                // command line of the shell running this query. pgrep exits with 1 when nothing
+                // matched, and processes may end before their stats are read.
+                format!(
+                    "echo '{}' && {{ pids=$(pgrep {}\"$(printf '{}')\") || [ $? -eq 1 ]; }} && \
+                     for p in $pids; do cat /proc/$p/stat 2>/dev/null || [ ! -e /proc/$p ]; done",
+                    SEPARATOR,
+                    full,
+                    octal_escape(pattern)
+                )
+            })
+            .collect();
+        let command = format!(
+            "(getconf PAGESIZE && getconf CLK_TCK && {}) 2>/dev/null",
+            watches.join(" && ")
+        );
+
+        Self {
+            names: configs.iter().map(|config| config.name.clone()).collect(),
+            command,
+            previous: None,
+        }
+    }
+}
+
+fn octal_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| format!("\\{:03o}", byte))
+        .collect()
+}
+
+/// Parses /proc/<pid>/stat lines, the process name may contain spaces and parentheses
+fn parse_stats(raw_data: &str) -> HashMap<ProcessId, ProcessStats> {
+    raw_data
+        .lines()
+        .filter_map(|line| {
+            let (pid, rest) = line.split_once(' ')?;
+            let (_, fields) = rest.rsplit_once(") ")?;
+            let fields: Vec<u64> = fields
+                .split_whitespace()
+                .map(|v| u64::from_str(v).unwrap_or(0))
+                .collect();
+            // Fields are numbered from the pid on, the state (3rd field) is the first one here
+            let field = |n: usize| fields.get(n - 3).cloned().unwrap_or(0);
+            let id = (u64::from_str(pid).ok()?, field(22));
+            let stats = ProcessStats {
+                cpu_ticks: field(14) + field(15),
+                rss_pages: field(24),
+            };
+            Some((id, stats))
+        })
+        .collect()
+}
+
+impl MetricPlugin for ProcessMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let separator = format!("{}\n", SEPARATOR);
+        let mut sections = raw_data.split(&separator);
+        let mut header = sections.next().unwrap_or("").lines();
+        let page_size = header
+            .next()
+            .and_then(|v| f64::from_str(v).ok())
+            .unwrap_or(4096.0);
+        let clock_ticks = header
+            .next()
+            .and_then(|v| f64::from_str(v).ok())
+            .unwrap_or(100.0);
+
+        let stats: Vec<HashMap<ProcessId, ProcessStats>> = self
+            .names
+            .iter()
+            .map(|_| parse_stats(sections.next().unwrap_or("")))
+            .collect();
+
+        let time_elapsed = self
+            .previous
+            .as_ref()
+            .and_then(|(_, previous_time)| timestamp.duration_since(*previous_time).ok())
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or(0.0);
+
+        let groups = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let previous = self.previous.as_ref().and_then(|(stats, _)| stats.get(i));
+                let ticks: u64 = stats[i]
+                    .iter()
+                    .filter_map(|(id, current)| {
+                        let previous = previous?.get(id)?;
+                        current.cpu_ticks.checked_sub(previous.cpu_ticks)
+                    })
+                    .sum();
+                let cpu_usage = if time_elapsed > 0.0 {
+                    ticks as f64 / clock_ticks / time_elapsed * 100.0
+                } else {
+                    0.0
+                };
+
+                ProcessGroup {
+                    name: name.clone(),
+                    count: stats[i].len() as f64,
+                    cpu_usage,
+                    rss: stats[i].values().map(|s| s.rss_pages as f64).sum::<f64>() * page_size,
+                }
+            })
+            .collect();
+
+        self.previous = Some((stats, *timestamp));
+
+        Metrics::Process(ProcessMetrics { groups })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Process(ProcessMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 = "4096
+100
+--
+812 (postgres) S 1 812 812 0 -1 4194560 9000 0 0 0 1000 500 0 0 20 0 1 0 2500 221184000 5000 18446744073709551615
+901 (postgres) S 812 901 901 0 -1 4194624 300 0 0 0 200 100 0 0 20 0 1 0 2600 221184000 1000 18446744073709551615
+--
+";
+        let raw_data_2 = "4096
+100
+--
+812 (postgres) S 1 812 812 0 -1 4194560 9000 0 0 0 1060 540 0 0 20 0 1 0 2500 221184000 5000 18446744073709551615
+901 (postgres) S 812 901 901 0 -1 4194624 300 0 0 0 230 110 0 0 20 0 1 0 2600 221184000 1200 18446744073709551615
+--
+";
+        let metrics = ProcessMetrics {
+            groups: vec![
+                ProcessGroup {
+                    name: "postgres".to_string(),
+                    count: 2.,
+                    cpu_usage: 140.,
+                    rss: 6200. * 4096.,
+                },
+                ProcessGroup {
+                    name: "worker".to_string(),
+                    count: 0.,
+                    cpu_usage: 0.,
+                    rss: 0.,
+                },
+            ],
+        };
+        assert_parse(raw_data_1, raw_data_2, metrics);
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let raw_data = "4242 (tmux: server) S 1 4242 4242 0 -1 4194368 1 0 0 0 7 3 0 0 20 0 1 0 9000 0 250 0\n";
+        let stats = parse_stats(raw_data);
+        assert_eq!(
+            stats.get(&(4242, 9000)),
+            Some(&ProcessStats {
+                cpu_ticks: 10,
+                rss_pages: 250
+            })
+        );
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: ProcessMetrics) {
+        let configs = vec![
+            WatchProcessConfig {
+                name: "postgres".to_string(),
+                pattern: None,
+                full_command: false,
+            },
+            WatchProcessConfig {
+                name: "worker".to_string(),
+                pattern: Some("python .*worker.py".to_string()),
+                full_command: true,
+            },
+        ];
+        let mut metric_plugin = ProcessMetricPlugin::new(&configs);
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Process(expected_metrics));
+    }
+}
@@ -0,0 +1,317 @@
+use super::{MetricPlugin, Metrics};
+use serde_derive::Serialize;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+const SEPARATOR: &str = "--";
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct RaidMetrics {
+    /// Number of arrays and pools that are not healthy
+    degraded: f64,
+    arrays: Vec<ArrayHealth>,
+}
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct ArrayHealth {
+    name: String,
+    /// md array state like active or degraded, or the ZFS pool health like ONLINE
+    state: String,
+    healthy: bool,
+    /// Running resync, recovery, reshape, check, resilver or scrub, empty when idle
+    operation: String,
+    /// Progress of the operation in percent
+    progress: f64,
+}
+
+/// Arrays are matched by name, an unhealthy state wins over a healthy one. Arrays of different
+/// nodes need to be told apart with `on_node` first.
+impl Add for RaidMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut arrays = self.arrays;
+        for array in other.arrays {
+            match arrays.iter_mut().find(|a| a.name == array.name) {
+                Some(existing) => {
+                    if !array.healthy || existing.healthy {
+                        *existing = array;
+                    }
+                }
+                None => arrays.push(array),
+            }
+        }
+        Self {
+            degraded: self.degraded + other.degraded,
+            arrays,
+        }
+    }
+}
+
+impl RaidMetrics {
+    /// Prefixes the array names with the hostname, md0 is found on many nodes
+    pub fn on_node(self, hostname: &str) -> Self {
+        let arrays = self
+            .arrays
+            .into_iter()
+            .map(|array| ArrayHealth {
+                name: format!("{}/{}", hostname, array.name),
+                ..array
+            })
+            .collect();
+        Self { arrays, ..self }
+    }
+
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            degraded: self.degraded / divisor,
+            ..self
+        }
+    }
+}
+
+/// Reports the health and rebuild progress of MD RAID arrays and ZFS pools
+pub struct RaidMetricPlugin {}
+
+impl RaidMetricPlugin {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl MetricPlugin for RaidMetricPlugin {
+    fn get_query(&self) -> &'static str {
+        // Either kind of array is optional, the md module may not be loaded and ZFS not installed.
+        // The parts are independent, so that the separator is there whatever the first one does.
+        "if [ -e /proc/mdstat ]; then cat /proc/mdstat; fi; echo '--'; \
+         if command -v zpool >/dev/null; then zpool status 2>/dev/null; fi"
+    }
+
+    fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
+        let separator = format!("{}\n", SEPARATOR);
+        let mut sections = raw_data.splitn(2, &separator);
+        let mut arrays = parse_mdstat(sections.next().unwrap_or(""));
+        arrays.append(&mut parse_zpool_status(sections.next().unwrap_or("")));
+
+        Metrics::Raid(RaidMetrics {
+            degraded: arrays.iter().filter(|array| !array.healthy).count() as f64,
+            arrays,
+        })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Raid(RaidMetrics::default())
+    }
+
+    /// zpool status is too slow to be run every second, array states rarely change
+    fn interval(&self) -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+/// Extracts the percentage from strings like `recovery = 12.6% (131072/1046528)`
+fn parse_progress(line: &str) -> Option<f64> {
+    let end = line.find('%')?;
+    let start = line[..end]
+        .rfind(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    f64::from_str(&line[start..end]).ok()
+}
+
+fn parse_mdstat(raw_data: &str) -> Vec<ArrayHealth> {
+    let mut arrays: Vec<ArrayHealth> = vec![];
+
+    for line in raw_data.lines() {
+        if let Some((name, description)) = line.split_once(" : ") {
+            if !name.starts_with("md") {
+                continue;
+            }
+            let state = description.split_whitespace().next().unwrap_or("");
+            arrays.push(ArrayHealth {
+                name: name.trim().to_string(),
+                state: state.to_string(),
+                healthy: state == "active",
+                ..ArrayHealth::default()
+            });
+            continue;
+        }
+
+        let array = match arrays.last_mut() {
+            Some(array) => array,
+            None => continue,
+        };
+        let line = line.trim();
+
+        // The member status looks like [3/2] [U_U], where _ marks a missing device
+        if let Some(members) = line
+            .split_whitespace()
+            .find(|part| part.starts_with('[') && part.contains('_'))
+        {
+            if members
+                .chars()
+                .all(|c| c == '[' || c == ']' || c == 'U' || c == '_')
+            {
+                array.state = "degraded".to_string();
+                array.healthy = false;
+            }
+        }
+
+        for operation in ["recovery", "resync", "reshape", "check"] {
+            if line.contains(&format!("{} =", operation)) {
+                array.operation = operation.to_string();
+                array.progress = parse_progress(line).unwrap_or(0.0);
+            }
+        }
+    }
+
+    arrays
+}
+
+fn parse_zpool_status(raw_data: &str) -> Vec<ArrayHealth> {
+    let mut pools: Vec<ArrayHealth> = vec![];
+
+    for line in raw_data.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("pool:") {
+            pools.push(ArrayHealth {
+                name: name.trim().to_string(),
+                ..ArrayHealth::default()
+            });
+            continue;
+        }
+
+        let pool = match pools.last_mut() {
+            Some(pool) => pool,
+            None => continue,
+        };
+        if let Some(state) = line.strip_prefix("state:") {
+            pool.state = state.trim().to_string();
+            pool.healthy = pool.state == "ONLINE";
+        } else if let Some(scan) = line.strip_prefix("scan:") {
+            if scan.contains("in progress") {
+                pool.operation = scan.split_whitespace().next().unwrap_or("").to_string();
+            }
+        } else if !pool.operation.is_empty() && line.contains("% done") {
+            pool.progress = parse_progress(line).unwrap_or(pool.progress);
+        }
+    }
+
+    pools
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let array = |healthy: bool| ArrayHealth {
+            name: "md0".to_string(),
+            state: if healthy {
+                "active"
+            } else {
+                "active, degraded"
+            }
+            .to_string(),
+            healthy,
+            ..ArrayHealth::default()
+        };
+        let metrics = |healthy: bool| RaidMetrics {
+            degraded: if healthy { 0. } else { 1. },
+            arrays: vec![array(healthy)],
+        };
+
+        let expected = RaidMetrics {
+            degraded: 1.,
+            arrays: vec![array(false)],
+        };
+        assert_eq!(metrics(false) + metrics(true), expected);
+        assert_eq!(metrics(true) + metrics(false), expected);
+
+        // The same array name on two nodes stays two arrays
+        let named = |name: &str| ArrayHealth {
+            name: name.to_string(),
+            ..array(false)
+        };
+        let expected = RaidMetrics {
+            degraded: 2.,
+            arrays: vec![named("web1/md0"), named("web2/md0")],
+        };
+        assert_eq!(
+            metrics(false).on_node("web1") + metrics(false).on_node("web2"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_process_data() {
+        let raw_data = "\
+Personalities : [raid1] [raid6] [raid5] [raid4]
+md0 : active raid1 sdb1[1] sda1[0]
+      1046528 blocks super 1.2 [2/2] [UU]
+      [=====>...............]  check = 27.3% (286208/1046528) finish=0.4min speed=28620K/sec
+
+md1 : active raid5 sdc1[3] sdb2[1](F) sda2[0]
+      2093056 blocks super 1.2 level 5, 512k chunk, algorithm 2 [3/2] [U_U]
+      [==>..................]  recovery = 12.6% (131072/1046528) finish=0.5min speed=26214K/sec
+
+unused devices: <none>
+--
+  pool: tank
+ state: DEGRADED
+  scan: resilver in progress since Sun Jul  2 10:00:00 2023
+\t150G resilvered, 30.00% done, 00:40:00 to go
+  pool: backup
+ state: ONLINE
+  scan: scrub repaired 0B in 01:02:03 with 0 errors on Sun Jul  2 02:00:00 2023
+";
+        let metrics = RaidMetrics {
+            degraded: 2.,
+            arrays: vec![
+                ArrayHealth {
+                    name: "md0".to_string(),
+                    state: "active".to_string(),
+                    healthy: true,
+                    operation: "check".to_string(),
+                    progress: 27.3,
+                },
+                ArrayHealth {
+                    name: "md1".to_string(),
+                    state: "degraded".to_string(),
+                    healthy: false,
+                    operation: "recovery".to_string(),
+                    progress: 12.6,
+                },
+                ArrayHealth {
+                    name: "tank".to_string(),
+                    state: "DEGRADED".to_string(),
+                    healthy: false,
+                    operation: "resilver".to_string(),
+                    progress: 30.,
+                },
+                ArrayHealth {
+                    name: "backup".to_string(),
+                    state: "ONLINE".to_string(),
+                    healthy: true,
+                    operation: "".to_string(),
+                    progress: 0.,
+                },
+            ],
+        };
+        assert_parse(raw_data, metrics);
+
+        let raw_data = "Personalities : \nunused devices: <none>\n--\n";
+        assert_parse(raw_data, RaidMetrics::default());
+        assert_parse("", RaidMetrics::default());
+    }
+
+    fn assert_parse(raw_data: &str, expected_metrics: RaidMetrics) {
+        let mut metric_plugin = RaidMetricPlugin::new();
+        let now = SystemTime::now();
+        let metrics = metric_plugin.process_data(raw_data, &now);
+
+        assert_eq!(metrics, Metrics::Raid(expected_metrics));
+    }
+}
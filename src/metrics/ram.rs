@@ -36,6 +36,10 @@ impl MetricPlugin for RamMetricPlugin {
         "cat /proc/meminfo"
     }
 
+    fn files(&self) -> Vec<&str> {
+        vec!["/proc/meminfo"]
+    }
+
     fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
         let mut mem_total = 0;
         let mut mem_free = 0;
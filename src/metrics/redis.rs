@@ -0,0 +1,267 @@
+use super::{counter_rate, shell_quote, MetricPlugin, Metrics};
+use crate::config::RedisConfig;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct RedisMetrics {
+    connected_clients: f64,
+    ops_per_second: f64,
+    /// Keyspace hits and misses per second, their ratio is the hit ratio
+    hits_per_second: f64,
+    misses_per_second: f64,
+    used_memory: f64,
+    /// Seconds since the last interaction with the master, or the largest lag of the replicas
+    replication_lag: f64,
+    /// 1 when a replica has lost the link to its master, the lag is unknown then
+    master_link_down: f64,
+}
+
+/// Replication lags don't add up, the largest one is kept, as is a down master link
+impl Add for RedisMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            connected_clients: self.connected_clients + other.connected_clients,
+            ops_per_second: self.ops_per_second + other.ops_per_second,
+            hits_per_second: self.hits_per_second + other.hits_per_second,
+            misses_per_second: self.misses_per_second + other.misses_per_second,
+            used_memory: self.used_memory + other.used_memory,
+            replication_lag: self.replication_lag.max(other.replication_lag),
+            master_link_down: self.master_link_down.max(other.master_link_down),
+        }
+    }
+}
+
+impl RedisMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            connected_clients: self.connected_clients / divisor,
+            ops_per_second: self.ops_per_second / divisor,
+            hits_per_second: self.hits_per_second / divisor,
+            misses_per_second: self.misses_per_second / divisor,
+            used_memory: self.used_memory / divisor,
+            replication_lag: self.replication_lag,
+            master_link_down: self.master_link_down,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RedisCounters {
+    commands: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// Collects clients, throughput, keyspace hits and replication state from `redis-cli INFO`
+pub struct RedisMetricPlugin {
+    command: String,
+    previous: Option<(RedisCounters, SystemTime)>,
+    /// Reported for the previous sample, its rates are reported again after a counter reset
+    last_metrics: RedisMetrics,
+}
+
+impl RedisMetricPlugin {
+    pub fn new(config: &RedisConfig) -> Self {
+        // redis-cli takes the password from the environment. A password from the config is
+        // part of the query and visible in ps for as long as the shell runs, one read from a
+        // file on the server never leaves it.
+        let auth = match (&config.password_file, &config.password) {
+            (Some(file), _) => format!("REDISCLI_AUTH=\"$(cat {})\" ", shell_quote(file)),
+            (None, Some(password)) => format!("REDISCLI_AUTH={} ", shell_quote(password)),
+            (None, None) => "".to_string(),
+        };
+        let command = format!(
+            "{}redis-cli -h {} -p {} INFO 2>/dev/null",
+            auth,
+            shell_quote(&config.host),
+            config.port
+        );
+        Self {
+            command,
+            previous: None,
+            last_metrics: RedisMetrics::default(),
+        }
+    }
+}
+
+impl MetricPlugin for RedisMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, timestamp: &SystemTime) -> Metrics {
+        let info: HashMap<&str, &str> = raw_data
+            .lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .collect();
+        if info.is_empty() {
+            return self.empty_metrics();
+        }
+
+        let number = |key: &str| {
+            info.get(key)
+                .and_then(|v| f64::from_str(v).ok())
+                .unwrap_or(0.0)
+        };
+        let counter = |key: &str| {
+            info.get(key)
+                .and_then(|v| u64::from_str(v).ok())
+                .unwrap_or(0)
+        };
+
+        let is_replica = info.get("role") == Some(&"slave");
+        // master_last_io_seconds_ago is -1 while the link is down
+        let master_link_down = is_replica && info.get("master_link_status") != Some(&"up");
+        let replication_lag = if master_link_down {
+            0.0
+        } else if is_replica {
+            number("master_last_io_seconds_ago")
+        } else {
+            // Replicas are listed like slave0:ip=10.0.0.2,port=6379,state=online,offset=42,lag=1
+            info.iter()
+                .filter(|(key, _)| key.starts_with("slave") && key[5..].parse::<u32>().is_ok())
+                .filter_map(|(_, value)| {
+                    value
+                        .split(',')
+                        .find_map(|field| field.strip_prefix("lag="))
+                        .and_then(|lag| f64::from_str(lag).ok())
+                })
+                .fold(0.0, f64::max)
+        };
+
+        let counters = RedisCounters {
+            commands: counter("total_commands_processed"),
+            hits: counter("keyspace_hits"),
+            misses: counter("keyspace_misses"),
+        };
+        let mut metrics = RedisMetrics {
+            connected_clients: number("connected_clients"),
+            used_memory: number("used_memory"),
+            replication_lag,
+            master_link_down: if master_link_down { 1.0 } else { 0.0 },
+            ..RedisMetrics::default()
+        };
+        if let Some((previous, previous_time)) = self.previous {
+            let time_elapsed = timestamp
+                .duration_since(previous_time)
+                .unwrap_or_default()
+                .as_secs_f64();
+            metrics.ops_per_second =
+                counter_rate(counters.commands, previous.commands, time_elapsed)
+                    .unwrap_or(self.last_metrics.ops_per_second);
+            metrics.hits_per_second = counter_rate(counters.hits, previous.hits, time_elapsed)
+                .unwrap_or(self.last_metrics.hits_per_second);
+            metrics.misses_per_second =
+                counter_rate(counters.misses, previous.misses, time_elapsed)
+                    .unwrap_or(self.last_metrics.misses_per_second);
+        }
+        self.previous = Some((counters, *timestamp));
+        self.last_metrics = metrics.clone();
+
+        Metrics::Redis(metrics)
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Redis(RedisMetrics::default())
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+        self.last_metrics = RedisMetrics::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_process_data() {
+        let raw_data_1 = "# Clients\r
+connected_clients:12\r
+# Memory\r
+used_memory:1048576\r
+# Stats\r
+total_commands_processed:1000\r
+keyspace_hits:800\r
+keyspace_misses:200\r
+# Replication\r
+role:master\r
+connected_slaves:2\r
+slave0:ip=10.0.0.2,port=6379,state=online,offset=4242,lag=0\r
+slave1:ip=10.0.0.3,port=6379,state=online,offset=4200,lag=3\r
+";
+        let raw_data_2 = raw_data_1
+            .replace(
+                "total_commands_processed:1000",
+                "total_commands_processed:1500",
+            )
+            .replace("keyspace_hits:800", "keyspace_hits:1150")
+            .replace("keyspace_misses:200", "keyspace_misses:250");
+        let metrics = RedisMetrics {
+            connected_clients: 12.,
+            ops_per_second: 500.,
+            hits_per_second: 350.,
+            misses_per_second: 50.,
+            used_memory: 1048576.,
+            replication_lag: 3.,
+            master_link_down: 0.,
+        };
+        assert_parse(raw_data_1, &raw_data_2, metrics);
+
+        let raw_data = "role:slave\r\nmaster_link_status:up\r\nmaster_last_io_seconds_ago:7\r\nconnected_clients:1\r\n";
+        let metrics = RedisMetrics {
+            connected_clients: 1.,
+            replication_lag: 7.,
+            ..RedisMetrics::default()
+        };
+        assert_parse(raw_data, raw_data, metrics);
+
+        let raw_data = "role:slave\r\nmaster_link_status:down\r\nmaster_last_io_seconds_ago:-1\r\nconnected_clients:1\r\n";
+        let metrics = RedisMetrics {
+            connected_clients: 1.,
+            master_link_down: 1.,
+            ..RedisMetrics::default()
+        };
+        assert_parse(raw_data, raw_data, metrics);
+        assert_parse("", "", RedisMetrics::default());
+    }
+
+    #[test]
+    fn test_password_file() {
+        let config = RedisConfig {
+            password: Some("hunter2".to_string()),
+            password_file: Some("/etc/redis/password".to_string()),
+            ..RedisConfig::default()
+        };
+
+        assert_eq!(
+            RedisMetricPlugin::new(&config).get_query(),
+            "REDISCLI_AUTH=\"$(cat '/etc/redis/password')\" redis-cli -h '127.0.0.1' -p 6379 \
+             INFO 2>/dev/null"
+        );
+    }
+
+    fn assert_parse(raw_data_1: &str, raw_data_2: &str, expected_metrics: RedisMetrics) {
+        let config = RedisConfig {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            password: None,
+            password_file: None,
+        };
+        let mut metric_plugin = RedisMetricPlugin::new(&config);
+        let now = UNIX_EPOCH + Duration::new(1531416624, 0);
+        metric_plugin.process_data(raw_data_1, &now);
+        let now = UNIX_EPOCH + Duration::new(1531416625, 0);
+        let metrics = metric_plugin.process_data(raw_data_2, &now);
+
+        assert_eq!(metrics, Metrics::Redis(expected_metrics));
+    }
+}
@@ -0,0 +1,153 @@
+use super::{shell_quote, MetricPlugin, Metrics};
+use crate::config::SystemdConfig;
+use serde_derive::Serialize;
+use std::ops::Add;
+use std::time::SystemTime;
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize)]
+pub struct SystemdMetrics {
+    failed: f64,
+    failed_units: Vec<String>,
+    critical_down: Vec<String>,
+}
+
+/// Unit names are merged rather than concatenated so that rollups list every unit only once
+impl Add for SystemdMetrics {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            failed: self.failed + other.failed,
+            failed_units: merge_names(self.failed_units, other.failed_units),
+            critical_down: merge_names(self.critical_down, other.critical_down),
+        }
+    }
+}
+
+impl SystemdMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            failed: self.failed / divisor,
+            ..self
+        }
+    }
+}
+
+fn merge_names(mut names: Vec<String>, other: Vec<String>) -> Vec<String> {
+    for name in other {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Reports failed systemd units and checks that the configured critical units are running
+pub struct SystemdMetricPlugin {
+    command: String,
+    critical_units: Vec<String>,
+}
+
+impl SystemdMetricPlugin {
+    pub fn new(config: &SystemdConfig) -> Self {
+        let critical_units = config.critical_units.clone();
+        let mut command =
+            "systemctl list-units --failed --plain --no-legend 2>/dev/null && echo '--'"
+                .to_string();
+        if !critical_units.is_empty() {
+            let units: Vec<String> = critical_units.iter().map(|u| shell_quote(u)).collect();
+            // is-active exits with 3 or 4 as soon as one of the units is not active or not
+            // known, only 1 means that systemd couldn't be asked
+            command += &format!(
+                " && {{ systemctl is-active {} 2>/dev/null || [ $? -ne 1 ]; }}",
+                units.join(" ")
+            );
+        }
+
+        Self {
+            command,
+            critical_units,
+        }
+    }
+}
+
+impl MetricPlugin for SystemdMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
+        let mut sections = raw_data.splitn(2, "--\n");
+        let failed_units: Vec<String> = sections
+            .next()
+            .unwrap_or("")
+            .lines()
+            .filter_map(|line| line.split_whitespace().find(|part| *part != "●"))
+            .map(|unit| unit.to_string())
+            .collect();
+
+        let states: Vec<&str> = sections
+            .next()
+            .unwrap_or("")
+            .lines()
+            .map(|line| line.trim())
+            .collect();
+        let critical_down = self
+            .critical_units
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| states.get(*i) != Some(&"active"))
+            .map(|(_, unit)| unit.to_string())
+            .collect();
+
+        Metrics::Systemd(SystemdMetrics {
+            failed: failed_units.len() as f64,
+            failed_units,
+            critical_down,
+        })
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Systemd(SystemdMetrics::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_process_data() {
+        let raw_data = "\
+logrotate.service loaded failed failed Rotate log files
+smartd.service    loaded failed failed Self Monitoring and Reporting Technology (SMART) Daemon
+--
+active
+inactive
+";
+        let metrics = SystemdMetrics {
+            failed: 2.,
+            failed_units: vec!["logrotate.service".into(), "smartd.service".into()],
+            critical_down: vec!["postgresql.service".into()],
+        };
+        assert_parse(raw_data, metrics);
+
+        let metrics = SystemdMetrics {
+            failed: 0.,
+            failed_units: vec![],
+            critical_down: vec!["nginx.service".into(), "postgresql.service".into()],
+        };
+        assert_parse("", metrics);
+    }
+
+    fn assert_parse(raw_data: &str, expected_metrics: SystemdMetrics) {
+        let config = SystemdConfig {
+            critical_units: vec!["nginx.service".into(), "postgresql.service".into()],
+        };
+        let mut metric_plugin = SystemdMetricPlugin::new(&config);
+        let now = SystemTime::now();
+        let metrics = metric_plugin.process_data(raw_data, &now);
+
+        assert_eq!(metrics, Metrics::Systemd(expected_metrics));
+    }
+}
@@ -0,0 +1,127 @@
+use super::{shell_quote, MetricPlugin, Metrics};
+use crate::config::UpdatesConfig;
+use derive_more::Add;
+use serde_derive::Serialize;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Outputs are taken before they are counted, so that a failing package manager fails the query.
+/// check-update exits with 100 when there are updates, servers without any package manager
+/// known here have none pending.
+const QUERY: &str = "(if command -v apt-get >/dev/null; then \
+     u=$(apt-get -s -o Debug::NoLocking=true upgrade) && echo \"$u\" | awk '/^Inst / {n++} \
+     /^Inst .*-security/ {s++} END {print \"pending\", n+0; print \"security\", s+0}'; \
+     elif command -v dnf >/dev/null || command -v yum >/dev/null; then \
+     m=$(command -v dnf || command -v yum); \
+     { u=$($m -q -C check-update) || [ $? -eq 100 ]; } && \
+     s=$($m -q -C updateinfo list --security) && \
+     echo \"pending $(echo \"$u\" | awk 'NF == 3 && $1 ~ /\\./' | wc -l)\" && \
+     echo \"security $(echo \"$s\" | awk 'NF >= 3' | wc -l)\"; \
+     elif command -v apk >/dev/null; then \
+     u=$(apk -u list) && echo \"pending $(echo \"$u\" | grep -c .)\"; \
+     fi && if [ -f /var/run/reboot-required ]; then echo 'reboot_required 1'; fi) 2>/dev/null";
+
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Add)]
+pub struct UpdateMetrics {
+    pending: f64,
+    security: f64,
+    reboot_required: f64,
+}
+
+impl UpdateMetrics {
+    pub fn divide(self, divisor: f64) -> Self {
+        Self {
+            pending: self.pending / divisor,
+            security: self.security / divisor,
+            reboot_required: self.reboot_required / divisor,
+        }
+    }
+}
+
+/// Counts pending package updates of the first package manager found and checks whether
+/// the server asks to be rebooted. Only the local package cache is read, it is never refreshed.
+pub struct UpdateMetricPlugin {
+    command: String,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl UpdateMetricPlugin {
+    pub fn new(config: &UpdatesConfig) -> Self {
+        Self {
+            // Stops the package manager on the server as well once the check is given up on
+            command: format!("timeout {} sh -c {}", config.timeout, shell_quote(QUERY)),
+            interval: Duration::from_secs(config.interval),
+            timeout: Duration::from_secs(config.timeout),
+        }
+    }
+}
+
+impl MetricPlugin for UpdateMetricPlugin {
+    fn get_query(&self) -> &str {
+        &self.command
+    }
+
+    fn process_data(&mut self, raw_data: &str, _: &SystemTime) -> Metrics {
+        let mut metrics = UpdateMetrics::default();
+        for line in raw_data.lines() {
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap_or("");
+            let value = parts
+                .next()
+                .and_then(|v| f64::from_str(v).ok())
+                .unwrap_or(0.0);
+            match key {
+                "pending" => metrics.pending = value,
+                "security" => metrics.security = value,
+                "reboot_required" => metrics.reboot_required = value,
+                _ => (),
+            }
+        }
+
+        Metrics::Updates(metrics)
+    }
+
+    fn empty_metrics(&self) -> Metrics {
+        Metrics::Updates(UpdateMetrics::default())
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Some(self.timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_process_data() {
+        let metrics = UpdateMetrics {
+            pending: 12.,
+            security: 3.,
+            reboot_required: 1.,
+        };
+        assert_parse("pending 12\nsecurity 3\nreboot_required 1\n", metrics);
+
+        let metrics = UpdateMetrics {
+            pending: 4.,
+            security: 0.,
+            reboot_required: 0.,
+        };
+        assert_parse("pending 4\n", metrics);
+        assert_parse("", UpdateMetrics::default());
+    }
+
+    fn assert_parse(raw_data: &str, expected_metrics: UpdateMetrics) {
+        let mut metric_plugin = UpdateMetricPlugin::new(&UpdatesConfig::default());
+        let now = SystemTime::now();
+        let metrics = metric_plugin.process_data(raw_data, &now);
+
+        assert_eq!(metrics, Metrics::Updates(expected_metrics));
+    }
+}
@@ -0,0 +1,89 @@
+use serde_derive::Serialize;
+use std::str::FromStr;
+
+/// Prints one `key=value` line per property, missing tools result in empty values
+pub const SYSTEM_INFO_QUERY: &str = "echo \"cpus=$(nproc)\"; \
+     echo \"kernel=$(uname -r)\"; \
+     echo \"architecture=$(uname -m)\"; \
+     echo \"os=$(. /etc/os-release 2>/dev/null; echo \"$PRETTY_NAME\")\"; \
+     echo \"cpu_model=$(grep -m1 -E '^(model name|Hardware|cpu model)' /proc/cpuinfo | cut -d: -f2-)\"; \
+     echo \"memory_total=$(awk '/^MemTotal:/ {print $2}' /proc/meminfo)\"; \
+     echo \"virtualization=$(systemd-detect-virt 2>/dev/null)\"; \
+     echo \"boot_id=$(cat /proc/sys/kernel/random/boot_id)\"";
+
+/// Properties of a server that are gathered once after connecting
+#[derive(Default, Clone, PartialEq, Debug, Serialize)]
+pub struct SystemInfo {
+    /// Already part of the node specs, only kept here to avoid an extra round trip
+    #[serde(skip)]
+    pub cpus: u16,
+    pub kernel: String,
+    pub architecture: String,
+    pub os: String,
+    pub cpu_model: String,
+    /// Total memory in KiB
+    pub memory_total: u64,
+    /// `none` on bare metal, empty when systemd-detect-virt is not available
+    pub virtualization: String,
+    /// Changes on every reboot
+    pub boot_id: String,
+}
+
+impl SystemInfo {
+    pub fn from_string(raw_data: &str) -> Self {
+        let mut info = Self::default();
+        for (key, value) in raw_data.lines().filter_map(|line| line.split_once('=')) {
+            let value = value.trim();
+            match key {
+                "cpus" => info.cpus = u16::from_str(value).unwrap_or(0),
+                "kernel" => info.kernel = value.to_string(),
+                "architecture" => info.architecture = value.to_string(),
+                "os" => info.os = value.to_string(),
+                "cpu_model" => info.cpu_model = value.to_string(),
+                "memory_total" => info.memory_total = u64::from_str(value).unwrap_or(0),
+                "virtualization" => info.virtualization = value.to_string(),
+                "boot_id" => info.boot_id = value.to_string(),
+                _ => (),
+            }
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_string() {
+        let raw_data = "cpus=8
+kernel=5.15.0-76-generic
+architecture=x86_64
+os=Ubuntu 22.04.2 LTS
+cpu_model= Intel(R) Xeon(R) CPU E5-2680 v4 @ 2.40GHz
+memory_total=32795520
+virtualization=kvm
+boot_id=6e3c1f56-0b2a-4a57-9d1c-2f8f1b4c7a90
+";
+        let info = SystemInfo {
+            cpus: 8,
+            kernel: "5.15.0-76-generic".to_string(),
+            architecture: "x86_64".to_string(),
+            os: "Ubuntu 22.04.2 LTS".to_string(),
+            cpu_model: "Intel(R) Xeon(R) CPU E5-2680 v4 @ 2.40GHz".to_string(),
+            memory_total: 32795520,
+            virtualization: "kvm".to_string(),
+            boot_id: "6e3c1f56-0b2a-4a57-9d1c-2f8f1b4c7a90".to_string(),
+        };
+        assert_eq!(SystemInfo::from_string(raw_data), info);
+
+        let raw_data = "cpus=2\nkernel=6.1.0\nos=\nvirtualization=\n";
+        let info = SystemInfo {
+            cpus: 2,
+            kernel: "6.1.0".to_string(),
+            ..SystemInfo::default()
+        };
+        assert_eq!(SystemInfo::from_string(raw_data), info);
+        assert_eq!(SystemInfo::from_string(""), SystemInfo::default());
+    }
+}
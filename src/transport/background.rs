@@ -0,0 +1,102 @@
+use super::{ConnectionError, Framing, QueryError, QueryResult, Transport, POLL_INTERVAL};
+use log::debug;
+use std::io::{ErrorKind, Read};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs a query that is too slow for the batches on a channel of its own. Its output is read on
+/// a thread until the query ends or its timeout has passed.
+pub struct BackgroundQuery {
+    result: Receiver<QueryResult>,
+    deadline: Instant,
+}
+
+impl BackgroundQuery {
+    pub fn start(
+        transport: &mut dyn Transport,
+        query: &str,
+        timeout: Duration,
+    ) -> Result<Self, ConnectionError> {
+        let framing = Framing::new();
+        let output = transport.spawn(&framing.join(&[query]))?;
+        let hostname = transport.get_hostname().to_string();
+        let deadline = Instant::now() + timeout;
+        let (sender, result) = mpsc::channel();
+        thread::spawn(move || {
+            let raw_data = read_output(output, deadline, &hostname);
+            // Nobody is listening anymore once the query has timed out
+            let _ = sender.send(framing.split(&raw_data, 1).remove(0));
+        });
+
+        Ok(Self { result, deadline })
+    }
+
+    /// Returns the result once the query has ended or taken too long, nothing while it runs
+    pub fn poll(&self) -> Option<QueryResult> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) if Instant::now() < self.deadline => None,
+            Err(TryRecvError::Empty) => Some(Err(QueryError::TimedOut)),
+            Err(TryRecvError::Disconnected) => Some(Err(QueryError::Missing)),
+        }
+    }
+}
+
+fn read_output(mut output: Box<dyn Read + Send>, deadline: Instant, hostname: &str) -> String {
+    let mut raw_data = vec![];
+    let mut buffer = [0; 4096];
+    loop {
+        match output.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => raw_data.extend_from_slice(&buffer[..length]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) && Instant::now() < deadline =>
+            {
+                thread::sleep(POLL_INTERVAL)
+            }
+            Err(e) => {
+                debug!("[{}] Reading the background query failed: {}", hostname, e);
+                break;
+            }
+        }
+    }
+    String::from_utf8_lossy(&raw_data).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    /// Has nothing to say on the first read, like a command that is still running
+    struct Slow(Option<Cursor<&'static str>>);
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match &mut self.0 {
+                Some(output) => output.read(buf),
+                None => {
+                    self.0 = Some(Cursor::new("pending 3\n"));
+                    Err(ErrorKind::WouldBlock.into())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_output() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(
+            read_output(Box::new(Slow(None)), deadline, "test"),
+            "pending 3\n"
+        );
+
+        // Past the deadline, the command is given up on
+        let deadline = Instant::now();
+        assert_eq!(read_output(Box::new(Slow(None)), deadline, "test"), "");
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+
+/// What a single query in a batch produced, its output or why there is none
+pub type QueryResult = Result<String, QueryError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    /// The query exited with a non-zero status
+    Failed(i32),
+    /// The batch ended before the query finished, e.g. because the shell was killed
+    Missing,
+    /// A query running apart from the batches didn't end within its timeout
+    TimedOut,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Failed(status) => write!(f, "exited with status {}", status),
+            QueryError::Missing => write!(f, "returned no result"),
+            QueryError::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Runs the queries of a batch one after the other and ends the output of each with a marker
+/// holding its exit status. Markers carry a random token, so output that happens to look like
+/// one can't cut a result short.
+pub struct Framing {
+    token: String,
+}
+
+impl Framing {
+    pub fn new() -> Self {
+        Self {
+            token: format!("{:016x}", rand::random::<u64>()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_token(token: &str) -> Self {
+        Self {
+            token: token.to_string(),
+        }
+    }
+
+    /// The queries are independent, each runs in a subshell of its own so that one that fails,
+    /// exits or changes the directory doesn't affect the others
+    pub fn join(&self, queries: &[&str]) -> String {
+        queries
+            .iter()
+            .map(|query| {
+                // The newline ends queries that end with a comment or a semicolon
+                format!("( {}\n); printf '{}%s>>' \"$?\"", query, self.prefix())
+            })
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+
+    /// Splits the output of a batch into one result per query
+    pub fn split(&self, raw_data: &str, count: usize) -> Vec<QueryResult> {
+        let prefix = self.prefix();
+        let mut results = Vec::with_capacity(count);
+        let mut rest = raw_data;
+
+        while results.len() < count {
+            let (output, after) = match rest.split_once(&prefix) {
+                Some(parts) => parts,
+                None => break,
+            };
+            let (status, after) = match after.split_once(">>") {
+                Some(parts) => parts,
+                None => break,
+            };
+
+            results.push(match status.parse::<i32>() {
+                Ok(0) => Ok(output.to_string()),
+                Ok(status) => Err(QueryError::Failed(status)),
+                Err(_) => Err(QueryError::Missing),
+            });
+            rest = after;
+        }
+
+        results.resize(count, Err(QueryError::Missing));
+        results
+    }
+
+    fn prefix(&self) -> String {
+        format!("<<hearth:{}:", self.token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_split() {
+        let framing = Framing::with_token("0123456789abcdef");
+        let raw_data = "0.42 0.30\n<<hearth:0123456789abcdef:0>>\
+                        <<hearth:0123456789abcdef:1>>\
+                        ###### <<hearth:fedcba9876543210:0>>\n<<hearth:0123456789abcdef:0>>";
+
+        assert_eq!(
+            framing.split(raw_data, 4),
+            vec![
+                Ok("0.42 0.30\n".to_string()),
+                Err(QueryError::Failed(1)),
+                Ok("###### <<hearth:fedcba9876543210:0>>\n".to_string()),
+                Err(QueryError::Missing),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let framing = Framing::with_token("0123456789abcdef");
+
+        assert_eq!(
+            framing.join(&["cat /proc/loadavg", "cat /sys/block/sda/stat"]),
+            "( cat /proc/loadavg\n); printf '<<hearth:0123456789abcdef:%s>>' \"$?\"; \
+             ( cat /sys/block/sda/stat\n); printf '<<hearth:0123456789abcdef:%s>>' \"$?\""
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let framing = Framing::with_token("0123456789abcdef");
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(framing.join(&["cd /; exit 3", "pwd", "echo ok"]))
+            .output()
+            .unwrap();
+
+        let current_dir = std::env::current_dir().unwrap();
+        assert_eq!(
+            framing.split(&String::from_utf8_lossy(&output.stdout), 3),
+            vec![
+                Err(QueryError::Failed(3)),
+                Ok(format!("{}\n", current_dir.display())),
+                Ok("ok\n".to_string()),
+            ]
+        );
+    }
+}
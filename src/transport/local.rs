@@ -0,0 +1,316 @@
+use super::{spawn_stream, stop_stream, ConnectionError, ConnectionState, ExecTimings, Transport};
+use crate::metrics::shell_quote;
+use crate::sysinfo::{SystemInfo, SYSTEM_INFO_QUERY};
+use log::info;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::process::{Child, Command};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
+
+/// Directories of the host that are looked for below the root in containerized deployments
+const HOST_DIRECTORIES: [&str; 3] = ["/proc/", "/sys/", "/etc/"];
+
+/// Runs queries with the local shell instead of connecting to a server, optionally through a
+/// command template that hands them on to a container or VM
+pub struct LocalTransport {
+    hostname: String,
+    root: Option<String>,
+    template: Option<String>,
+    initialized: bool,
+    cpus: u8,
+    uptime_seconds: u64,
+    timings: ExecTimings,
+    system_info: SystemInfo,
+    state: ConnectionState,
+    stream: Option<Child>,
+    /// Process of the slow command that runs next to the batches
+    background: Option<Child>,
+}
+
+impl LocalTransport {
+    pub fn new(hostname: String, root: Option<String>, template: Option<String>) -> Self {
+        Self {
+            hostname,
+            root,
+            template,
+            initialized: false,
+            cpus: 0,
+            uptime_seconds: 0,
+            timings: ExecTimings::default(),
+            system_info: SystemInfo::default(),
+            state: ConnectionState::default(),
+            stream: None,
+            background: None,
+        }
+    }
+
+    /// Gathers information about the system, like the SSH client does after connecting
+    fn init(&mut self) {
+        self.initialized = true;
+        match &self.template {
+            Some(template) => info!("[{}] Collecting through {}", self.hostname, template),
+            None => info!("[{}] Collecting locally", self.hostname),
+        }
+
+        let system_info = self.run(SYSTEM_INFO_QUERY).unwrap_or_default();
+        self.system_info = SystemInfo::from_string(&system_info);
+        self.cpus = self.system_info.cpus as u8;
+        self.update_uptime();
+    }
+
+    fn command(&self, command: &str) -> Command {
+        let mut command = match &self.root {
+            Some(root) => rebase_paths(command, root),
+            None => command.to_string(),
+        };
+        if let Some(template) = &self.template {
+            command = expand_template(template, &self.hostname, &command);
+        }
+
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+
+    fn exec(&mut self, command: &str) -> Result<String, ConnectionError> {
+        let started = Instant::now();
+        self.timings.sent = Some(SystemTime::now());
+        let output = self
+            .command(command)
+            .output()
+            .map_err(|e| ConnectionError::CommandFailed(e.to_string()))?;
+        self.timings.command = started.elapsed();
+
+        // Queries always print something, a failure without output means that the shell or the
+        // container behind the template couldn't be reached
+        if !output.status.success() && output.stdout.is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ConnectionError::CommandFailed(format!(
+                "{}: {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Transport for LocalTransport {
+    fn run(&mut self, command: &str) -> Result<String, ConnectionError> {
+        if !self.initialized {
+            self.init();
+        }
+
+        let result = self.exec(command);
+        self.state.update(result.as_ref().map(|_| ()));
+        result
+    }
+
+    fn stream(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError> {
+        if !self.initialized {
+            self.init();
+        }
+
+        let result = spawn_stream(&mut self.command(command), &mut self.stream);
+        self.state.update(result.as_ref().map(|_| ()));
+        result
+    }
+
+    fn spawn(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError> {
+        if !self.initialized {
+            self.init();
+        }
+
+        spawn_stream(&mut self.command(command), &mut self.background)
+    }
+
+    /// Only when running on the host itself, commands of a template run elsewhere
+    fn can_read_files(&self) -> bool {
+        self.template.is_none()
+    }
+
+    /// Reads the files below the root like the queries would, without starting a shell
+    fn read_files(&mut self, paths: &[&str]) -> Result<Vec<String>, ConnectionError> {
+        if !self.initialized {
+            self.init();
+        }
+
+        let started = Instant::now();
+        let result: Result<Vec<String>, ConnectionError> = paths
+            .iter()
+            .map(|path| {
+                let path = match &self.root {
+                    Some(root) => rebase_paths(path, root),
+                    None => path.to_string(),
+                };
+                match fs::read_to_string(&path) {
+                    Ok(content) => Ok(content),
+                    Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+                    Err(e) => Err(ConnectionError::CommandFailed(format!(
+                        "Can't read {}: {}",
+                        path, e
+                    ))),
+                }
+            })
+            .collect();
+        self.timings.command = started.elapsed();
+        self.state.update(result.as_ref().map(|_| ()));
+        result
+    }
+
+    fn update_uptime(&mut self) {
+        let raw_uptime = self
+            .run("cat /proc/uptime")
+            .unwrap_or_else(|_| "0".to_string());
+        let uptime_seconds = raw_uptime.split_whitespace().next().unwrap_or("0");
+        self.uptime_seconds = f64::from_str(uptime_seconds).unwrap_or(0.0) as u64;
+    }
+
+    fn get_hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    fn get_ip(&self) -> &str {
+        match self.template {
+            Some(_) => "exec",
+            None => "local",
+        }
+    }
+
+    fn get_cpus(&self) -> u8 {
+        self.cpus
+    }
+
+    fn get_uptime(&self) -> u64 {
+        self.uptime_seconds
+    }
+
+    fn get_system_info(&self) -> &SystemInfo {
+        &self.system_info
+    }
+
+    fn get_timings(&self) -> ExecTimings {
+        self.timings
+    }
+
+    fn reset_timings(&mut self) {
+        self.timings = ExecTimings::default();
+    }
+
+    fn get_state(&self) -> &ConnectionState {
+        &self.state
+    }
+}
+
+impl Drop for LocalTransport {
+    fn drop(&mut self) {
+        stop_stream(&mut self.stream);
+        stop_stream(&mut self.background);
+    }
+}
+
+/// Fills in the server name and the quoted query, placeholders are replaced in a single pass so
+/// that braces inside the name can't be mistaken for them
+fn expand_template(template: &str, name: &str, command: &str) -> String {
+    let mut expanded = String::with_capacity(template.len() + command.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{name}") {
+            expanded.push_str(&shell_quote(name));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{cmd}") {
+            expanded.push_str(&shell_quote(command));
+            rest = after;
+        } else {
+            expanded.push('{');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Prefixes the absolute paths below the host directories in a command with the root
+fn rebase_paths(command: &str, root: &str) -> String {
+    let root = root.trim_end_matches('/');
+    let mut rebased = String::with_capacity(command.len());
+    let mut previous = ' ';
+    for (index, c) in command.char_indices() {
+        let starts_path = previous.is_whitespace() || "'\"(=<".contains(previous);
+        if c == '/'
+            && starts_path
+            && HOST_DIRECTORIES
+                .iter()
+                .any(|directory| command[index..].starts_with(directory))
+        {
+            rebased.push_str(root);
+        }
+        rebased.push(c);
+        previous = c;
+    }
+    rebased
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_template() {
+        assert_eq!(
+            expand_template(
+                "docker exec {name} sh -c {cmd}",
+                "web-1",
+                "cat /proc/loadavg"
+            ),
+            "docker exec 'web-1' sh -c 'cat /proc/loadavg'"
+        );
+        assert_eq!(
+            expand_template("lxc exec {name} -- sh -c {cmd}", "{cmd}", "echo 'a' {x}"),
+            "lxc exec '{cmd}' -- sh -c 'echo '\\''a'\\'' {x}'"
+        );
+    }
+
+    #[test]
+    fn test_read_files() {
+        let root = std::env::temp_dir().join(format!("hearth-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("proc")).unwrap();
+        fs::write(root.join("proc/loadavg"), "0.50 0.25 0.10 1/100 42\n").unwrap();
+
+        let mut transport =
+            LocalTransport::new("test".to_string(), root.to_str().map(String::from), None);
+        transport.initialized = true;
+        let contents = transport.read_files(&["/proc/loadavg", "/proc/vmstat"]);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            contents,
+            Ok(vec![
+                "0.50 0.25 0.10 1/100 42\n".to_string(),
+                "".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_rebase_paths() {
+        assert_eq!(
+            rebase_paths("cat /proc/stat && cd /sys/fs/cgroup", "/host/"),
+            "cat /host/proc/stat && cd /host/sys/fs/cgroup"
+        );
+        assert_eq!(
+            rebase_paths(
+                "(. /etc/os-release; echo \"id=$(cat /proc/sys/kernel/random/boot_id)\")",
+                "/host"
+            ),
+            "(. /host/etc/os-release; echo \"id=$(cat /host/proc/sys/kernel/random/boot_id)\")"
+        );
+        assert_eq!(
+            rebase_paths("df /var/lib /procedure a/proc/b", "/host"),
+            "df /var/lib /procedure a/proc/b"
+        );
+    }
+}
@@ -0,0 +1,250 @@
+mod background;
+mod framing;
+mod local;
+mod openssh;
+mod ssh;
+mod stream;
+
+pub use background::BackgroundQuery;
+use framing::Framing;
+pub use framing::{QueryError, QueryResult};
+use stream::{spawn_stream, stop_stream};
+pub use stream::{Sample, SampleStream};
+
+use crate::config::{AuthMethod, ReconnectConfig, ServerConfig, TransportConfig};
+use crate::sysinfo::SystemInfo;
+use log::warn;
+use serde_derive::Serialize;
+use std::fmt;
+use std::io::Read;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long to wait before reading again from a command that has nothing to say yet
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interface for the ways of running queries on a server and reading back their raw output
+pub trait Transport: Send + 'static {
+    /// Runs a single command and returns what it printed
+    fn run(&mut self, command: &str) -> Result<String, ConnectionError>;
+
+    /// Runs all queries in one go and returns their results in the same order
+    fn fetch(&mut self, queries: &[&str]) -> Result<Vec<QueryResult>, ConnectionError> {
+        let framing = Framing::new();
+        let raw_data = self.run(&framing.join(queries))?;
+        Ok(framing.split(&raw_data, queries.len()))
+    }
+
+    /// Starts a command that keeps running and returns its output as it is printed. Reads may
+    /// fail with `WouldBlock` while no new output has arrived. The command is stopped once the
+    /// output is dropped or the next stream is started.
+    fn stream(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError>;
+
+    /// Starts a slow command next to the batches and the stream and returns its output. Reads may
+    /// fail with `WouldBlock` or `TimedOut` while the command is still running. The previous
+    /// command is stopped when the next one is started.
+    fn spawn(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError>;
+
+    /// Whether `read_files` can be used instead of running queries that only read files
+    fn can_read_files(&self) -> bool {
+        false
+    }
+
+    /// Reads whole files without running a command, files that don't exist come back empty
+    fn read_files(&mut self, _paths: &[&str]) -> Result<Vec<String>, ConnectionError> {
+        Err(ConnectionError::CommandFailed(
+            "Files can't be read directly".to_string(),
+        ))
+    }
+
+    fn update_uptime(&mut self);
+
+    fn get_hostname(&self) -> &str;
+
+    fn get_ip(&self) -> &str;
+
+    fn get_cpus(&self) -> u8;
+
+    fn get_uptime(&self) -> u64;
+
+    fn get_system_info(&self) -> &SystemInfo;
+
+    fn get_timings(&self) -> ExecTimings;
+
+    fn reset_timings(&mut self);
+
+    fn get_state(&self) -> &ConnectionState;
+}
+
+/// Creates the transport that is configured for the given server
+pub fn transport_factory(
+    server_config: &ServerConfig,
+    auth_methods: Vec<AuthMethod>,
+    reconnect: ReconnectConfig,
+) -> Box<dyn Transport> {
+    match &server_config.transport {
+        TransportConfig::Ssh => Box::new(ssh::SshClient::new(
+            server_config.username.clone(),
+            auth_methods,
+            server_config.hostname.clone(),
+            22,
+            reconnect,
+        )),
+        TransportConfig::Local(config) => Box::new(local::LocalTransport::new(
+            server_config.hostname.clone(),
+            config.root.clone(),
+            None,
+        )),
+        TransportConfig::Openssh(config) => Box::new(openssh::OpensshTransport::new(
+            server_config.username.clone(),
+            server_config.hostname.clone(),
+            config.clone(),
+            reconnect,
+        )),
+        TransportConfig::Exec(config) => Box::new(local::LocalTransport::new(
+            server_config.hostname.clone(),
+            None,
+            Some(config.command.clone()),
+        )),
+    }
+}
+
+/// Reasons for not being able to run a command on a server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum ConnectionError {
+    Dns(String),
+    TcpTimeout,
+    ConnectionRefused,
+    Network(String),
+    Handshake(String),
+    AuthRejected(String),
+    /// The server presented a different key than the one in known_hosts
+    HostKeyMismatch,
+    CommandFailed(String),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Dns(message) => write!(f, "Name resolution failed: {}", message),
+            ConnectionError::TcpTimeout => write!(f, "TCP connection timed out"),
+            ConnectionError::ConnectionRefused => write!(f, "Connection refused"),
+            ConnectionError::Network(message) => write!(f, "Network error: {}", message),
+            ConnectionError::Handshake(message) => write!(f, "SSH handshake failed: {}", message),
+            ConnectionError::AuthRejected(message) => {
+                write!(f, "Authentication rejected: {}", message)
+            }
+            ConnectionError::HostKeyMismatch => write!(f, "Host key does not match known_hosts"),
+            ConnectionError::CommandFailed(message) => write!(f, "Command failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// Whether commands can currently be run on the server, and since when that is the case
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct ConnectionState {
+    connected: bool,
+    /// Unix timestamp of the last change between connected and failing, 0 before the first attempt
+    since: u64,
+    last_error: Option<ConnectionError>,
+    /// Unix timestamp of the next attempt to connect, 0 when there is no failure to recover from
+    retry_at: u64,
+}
+
+impl ConnectionState {
+    fn update(&mut self, result: Result<(), &ConnectionError>) {
+        let connected = result.is_ok();
+        if connected != self.connected || self.since == 0 {
+            self.since = unix_time(SystemTime::now());
+        }
+        self.connected = connected;
+        if let Err(error) = result {
+            self.last_error = Some(error.clone());
+        }
+    }
+
+    /// Time until the next attempt to connect is due, at least a second
+    pub fn retry_delay(&self) -> Duration {
+        let now = unix_time(SystemTime::now());
+        Duration::from_secs(self.retry_at.saturating_sub(now).max(1))
+    }
+
+    /// The error that is reported while waiting for the next attempt
+    fn waiting_error(&self) -> ConnectionError {
+        self.last_error
+            .clone()
+            .unwrap_or_else(|| ConnectionError::CommandFailed("Waiting to reconnect".to_string()))
+    }
+}
+
+/// Spaces out the attempts to reach a server that failed, following the reconnect config
+struct Backoff {
+    reconnect: ReconnectConfig,
+    /// Failed attempts in a row, they determine the delay before the next one
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    fn new(reconnect: ReconnectConfig) -> Self {
+        Self {
+            reconnect,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// Whether the next attempt is not due yet
+    fn waiting(&self) -> bool {
+        matches!(self.retry_at, Some(retry_at) if Instant::now() < retry_at)
+    }
+
+    /// Updates the connection state with the outcome of an attempt and schedules the next one
+    /// after a failure
+    fn record(
+        &mut self,
+        state: &mut ConnectionState,
+        hostname: &str,
+        result: Result<(), &ConnectionError>,
+    ) {
+        state.update(result);
+        match result {
+            Ok(()) => {
+                self.failures = 0;
+                self.retry_at = None;
+                state.retry_at = 0;
+            }
+            Err(error) => {
+                self.failures += 1;
+                let delay = self.reconnect.delay(self.failures);
+                warn!(
+                    "[{}] {}, retrying in {:.1} s",
+                    hostname,
+                    error,
+                    delay.as_secs_f64()
+                );
+                self.retry_at = Some(Instant::now() + delay);
+                state.retry_at = unix_time(SystemTime::now() + delay);
+            }
+        }
+    }
+}
+
+/// Time spent in the individual steps of running a command on the server
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ExecTimings {
+    /// Only set when the connection had to be (re)established
+    pub connect: Duration,
+    pub channel: Duration,
+    pub command: Duration,
+    /// When the command was handed to the server, after connecting and opening the channel
+    pub sent: Option<SystemTime>,
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,390 @@
+use super::{
+    spawn_stream, stop_stream, Backoff, ConnectionError, ConnectionState, ExecTimings, Transport,
+};
+use crate::config::{OpensshConfig, ReconnectConfig};
+use crate::sysinfo::{SystemInfo, SYSTEM_INFO_QUERY};
+use log::{info, warn};
+use std::fs;
+use std::io::Read;
+use std::net::ToSocketAddrs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
+
+/// Runs queries with the system's ssh binary, so that everything in ssh_config works. A master
+/// connection per host is kept open and shared by all queries.
+pub struct OpensshTransport {
+    username: String,
+    hostname: String,
+    config: OpensshConfig,
+    control_dir: PathBuf,
+    initialized: bool,
+    cpus: u8,
+    uptime_seconds: u64,
+    ip: String,
+    timings: ExecTimings,
+    system_info: SystemInfo,
+    state: ConnectionState,
+    backoff: Backoff,
+    stream: Option<Child>,
+    /// Process of the slow command that runs next to the batches
+    background: Option<Child>,
+}
+
+impl OpensshTransport {
+    pub fn new(
+        username: String,
+        hostname: String,
+        config: OpensshConfig,
+        reconnect: ReconnectConfig,
+    ) -> Self {
+        let control_dir = config
+            .control_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| std::env::temp_dir().join("hearth-ssh"));
+
+        Self {
+            username,
+            hostname,
+            config,
+            control_dir,
+            initialized: false,
+            cpus: 0,
+            uptime_seconds: 0,
+            ip: "".to_string(),
+            timings: ExecTimings::default(),
+            system_info: SystemInfo::default(),
+            state: ConnectionState::default(),
+            backoff: Backoff::new(reconnect),
+            stream: None,
+            background: None,
+        }
+    }
+
+    /// Gathers information about the system, the first query also brings up the master connection
+    fn init(&mut self) -> Result<(), ConnectionError> {
+        info!("[{}] Connecting with ssh", self.hostname);
+        if let Err(error) = fs::create_dir_all(&self.control_dir)
+            .and_then(|_| fs::set_permissions(&self.control_dir, fs::Permissions::from_mode(0o700)))
+        {
+            warn!(
+                "[{}] Can't prepare {}: {}",
+                self.hostname,
+                self.control_dir.display(),
+                error
+            );
+        }
+
+        let started = Instant::now();
+        let system_info = self.exec(SYSTEM_INFO_QUERY)?;
+        self.timings.connect = started.elapsed();
+        info!("[{}] Connection established", self.hostname);
+
+        self.system_info = SystemInfo::from_string(&system_info);
+        self.cpus = self.system_info.cpus as u8;
+        self.ip = self.resolve_ip();
+        self.initialized = true;
+        self.read_uptime();
+        Ok(())
+    }
+
+    /// Runs the query without going through `run`, which would try to connect once more
+    fn read_uptime(&mut self) {
+        let raw_uptime = self
+            .exec("cat /proc/uptime")
+            .unwrap_or_else(|_| "0".to_string());
+        let uptime_seconds = raw_uptime.split_whitespace().next().unwrap_or("0");
+        self.uptime_seconds = f64::from_str(uptime_seconds).unwrap_or(0.0) as u64;
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-n")
+            .arg("-T")
+            .args(["-o", "BatchMode=yes"])
+            .args(["-o", "ControlMaster=auto"])
+            .arg("-o")
+            .arg(format!(
+                "ControlPath={}",
+                self.control_dir.join("%C").display()
+            ))
+            .arg("-o")
+            .arg(format!("ControlPersist={}", self.config.control_persist));
+        // The first value of an option wins, the configured ones take precedence over the
+        // defaults that keep an unreachable host from blocking the aggregator for minutes
+        for option in &self.config.options {
+            command.arg("-o").arg(option);
+        }
+        command
+            .args(["-o", "ConnectTimeout=5"])
+            .args(["-o", "ServerAliveInterval=5"])
+            .args(["-o", "ServerAliveCountMax=2"]);
+        if let Some(port) = self.config.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        if !self.username.is_empty() {
+            command.arg("-l").arg(&self.username);
+        }
+        command
+    }
+
+    fn exec(&mut self, query: &str) -> Result<String, ConnectionError> {
+        let started = Instant::now();
+        self.timings.sent = Some(SystemTime::now());
+        let output = self
+            .command()
+            .arg(&self.hostname)
+            .arg("--")
+            .arg(query)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| ConnectionError::CommandFailed(format!("Can't run ssh: {}", e)))?;
+        self.timings.command = started.elapsed();
+
+        check_output(&output)?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Looks up the address of the host that ssh_config maps the hostname to
+    fn resolve_ip(&self) -> String {
+        let output = match self.command().arg("-G").arg(&self.hostname).output() {
+            Ok(output) => output,
+            Err(_) => return "".to_string(),
+        };
+        let config = String::from_utf8_lossy(&output.stdout);
+        let host = config
+            .lines()
+            .find_map(|line| line.strip_prefix("hostname "))
+            .unwrap_or(&self.hostname)
+            .trim()
+            .to_string();
+
+        match (host.as_str(), 0).to_socket_addrs() {
+            Ok(mut addresses) => addresses
+                .next()
+                .map(|address| address.ip().to_string())
+                .unwrap_or(host),
+            Err(_) => host,
+        }
+    }
+}
+
+impl Transport for OpensshTransport {
+    fn run(&mut self, command: &str) -> Result<String, ConnectionError> {
+        if self.backoff.waiting() {
+            return Err(self.state.waiting_error());
+        }
+
+        let result = if self.initialized {
+            self.exec(command)
+        } else {
+            self.init().and_then(|_| self.exec(command))
+        };
+        self.backoff
+            .record(&mut self.state, &self.hostname, result.as_ref().map(|_| ()));
+        if result.is_err() {
+            // System information is gathered again once the server is back, it may have rebooted
+            self.initialized = false;
+        }
+        result
+    }
+
+    fn stream(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError> {
+        if self.backoff.waiting() {
+            return Err(self.state.waiting_error());
+        }
+        if !self.initialized {
+            let result = self.init();
+            self.backoff
+                .record(&mut self.state, &self.hostname, result.as_ref().map(|_| ()));
+            result?;
+        }
+
+        let mut ssh = self.command();
+        ssh.arg(&self.hostname).arg("--").arg(command);
+        spawn_stream(&mut ssh, &mut self.stream)
+    }
+
+    /// Only runs on an established master connection, the batches take care of connecting
+    fn spawn(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError> {
+        if self.backoff.waiting() || !self.initialized {
+            return Err(self.state.waiting_error());
+        }
+
+        let mut ssh = self.command();
+        ssh.arg(&self.hostname).arg("--").arg(command);
+        spawn_stream(&mut ssh, &mut self.background)
+    }
+
+    /// Left for the next connect while the server is unreachable
+    fn update_uptime(&mut self) {
+        if self.initialized && !self.backoff.waiting() {
+            self.read_uptime();
+        }
+    }
+
+    fn get_hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    fn get_ip(&self) -> &str {
+        &self.ip
+    }
+
+    fn get_cpus(&self) -> u8 {
+        self.cpus
+    }
+
+    fn get_uptime(&self) -> u64 {
+        self.uptime_seconds
+    }
+
+    fn get_system_info(&self) -> &SystemInfo {
+        &self.system_info
+    }
+
+    fn get_timings(&self) -> ExecTimings {
+        self.timings
+    }
+
+    fn reset_timings(&mut self) {
+        self.timings = ExecTimings::default();
+    }
+
+    fn get_state(&self) -> &ConnectionState {
+        &self.state
+    }
+}
+
+impl Drop for OpensshTransport {
+    fn drop(&mut self) {
+        stop_stream(&mut self.stream);
+        stop_stream(&mut self.background);
+    }
+}
+
+/// ssh exits with 255 when it couldn't run the command, the reason is only found in its messages
+fn check_output(output: &Output) -> Result<(), ConnectionError> {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr.trim().lines().last().unwrap_or("").to_string();
+
+    match output.status.code() {
+        Some(255) => Err(classify_error(&stderr, message)),
+        Some(_) if !output.status.success() && output.stdout.is_empty() => {
+            Err(ConnectionError::CommandFailed(message))
+        }
+        None => Err(ConnectionError::CommandFailed(
+            "ssh was killed by a signal".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn classify_error(stderr: &str, message: String) -> ConnectionError {
+    if stderr.contains("REMOTE HOST IDENTIFICATION HAS CHANGED")
+        || stderr.contains("Host key verification failed")
+    {
+        ConnectionError::HostKeyMismatch
+    } else if stderr.contains("Could not resolve hostname") {
+        ConnectionError::Dns(message)
+    } else if stderr.contains("timed out") {
+        ConnectionError::TcpTimeout
+    } else if stderr.contains("Connection refused") {
+        ConnectionError::ConnectionRefused
+    } else if stderr.contains("Permission denied") || stderr.contains("Too many authentication") {
+        ConnectionError::AuthRejected(message)
+    } else if stderr.contains("kex_exchange_identification")
+        || stderr.contains("Connection closed by")
+        || stderr.contains("Unable to negotiate")
+    {
+        ConnectionError::Handshake(message)
+    } else {
+        ConnectionError::Network(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    /// Runs queries against a fake ssh on PATH that executes the command locally
+    #[test]
+    fn test_run() {
+        let dir = env::temp_dir().join(format!("hearth-openssh-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let ssh = dir.join("ssh");
+        fs::write(
+            &ssh,
+            "#!/bin/sh\n\
+             for arg; do [ \"$arg\" = -G ] && echo 'hostname 127.0.0.1' && exit; done\n\
+             for arg; do command=$arg; done\n\
+             exec sh -c \"$command\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = env::var("PATH").unwrap_or_default();
+        env::set_var("PATH", format!("{}:{}", dir.display(), path));
+
+        let config = OpensshConfig {
+            control_dir: Some(dir.join("control").display().to_string()),
+            ..OpensshConfig::default()
+        };
+        let mut transport = OpensshTransport::new(
+            "user".to_string(),
+            "box".to_string(),
+            config,
+            ReconnectConfig::default(),
+        );
+        let output = transport.run("echo hello");
+        transport.update_uptime();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(output, Ok("hello\n".to_string()));
+        assert!(transport.get_uptime() > 0);
+        assert_eq!(transport.get_ip(), "127.0.0.1");
+    }
+
+    #[test]
+    fn test_classify_error() {
+        let cases = [
+            (
+                "ssh: Could not resolve hostname nope: Name or service not known",
+                ConnectionError::Dns(
+                    "ssh: Could not resolve hostname nope: Name or service not known".to_string(),
+                ),
+            ),
+            (
+                "ssh: connect to host 10.0.0.1 port 22: Connection timed out",
+                ConnectionError::TcpTimeout,
+            ),
+            (
+                "ssh: connect to host 127.0.0.1 port 22: Connection refused",
+                ConnectionError::ConnectionRefused,
+            ),
+            (
+                "user@host: Permission denied (publickey).",
+                ConnectionError::AuthRejected("user@host: Permission denied (publickey).".to_string()),
+            ),
+            (
+                "@@@@@@@@\n@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @\nHost key verification failed.",
+                ConnectionError::HostKeyMismatch,
+            ),
+            (
+                "ssh: connect to host 10.0.0.1 port 22: No route to host",
+                ConnectionError::Network(
+                    "ssh: connect to host 10.0.0.1 port 22: No route to host".to_string(),
+                ),
+            ),
+        ];
+
+        for (stderr, error) in cases {
+            let message = stderr.trim().lines().last().unwrap_or("").to_string();
+            assert_eq!(classify_error(stderr, message), error);
+        }
+    }
+}
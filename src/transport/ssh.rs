@@ -0,0 +1,555 @@
+use super::{Backoff, ConnectionError, ConnectionState, ExecTimings, Transport};
+use crate::config::{AuthMethod, ReconnectConfig};
+use crate::sysinfo::{SystemInfo, SYSTEM_INFO_QUERY};
+use log::{debug, info, warn};
+use ssh2::{
+    Channel, CheckResult, ErrorCode, KeyboardInteractivePrompt, KnownHostFileKind, Prompt, Session,
+    Sftp,
+};
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Servers that are backed off from are probed at most this often
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct SshClient {
+    username: String,
+    auth_methods: Vec<AuthMethod>,
+    hostname: String,
+    port: usize,
+    session: Option<Session>,
+    /// SFTP subsystem of the session, opened on the first read of files
+    sftp: Option<Sftp>,
+    /// Set when the server has no SFTP subsystem, files are read with queries then
+    sftp_unavailable: bool,
+    cpus: u8,
+    uptime_seconds: u64,
+    ip: String,
+    timings: ExecTimings,
+    system_info: SystemInfo,
+    /// Modification times of the certificate files when the session was authenticated
+    certificates_modified: Vec<Option<SystemTime>>,
+    state: ConnectionState,
+    backoff: Backoff,
+    probe: Option<Probe>,
+}
+
+impl SshClient {
+    pub fn new(
+        username: String,
+        auth_methods: Vec<AuthMethod>,
+        hostname: String,
+        port: usize,
+        reconnect: ReconnectConfig,
+    ) -> Self {
+        SshClient {
+            username,
+            auth_methods,
+            hostname,
+            port,
+            session: None,
+            sftp: None,
+            sftp_unavailable: false,
+            cpus: 0, //@TODO Move to cpu module. Can be extracted from /proc/stat
+            uptime_seconds: 0,
+            ip: "".to_string(),
+            timings: ExecTimings::default(),
+            system_info: SystemInfo::default(),
+            certificates_modified: vec![],
+            state: ConnectionState::default(),
+            backoff: Backoff::new(reconnect),
+            probe: None,
+        }
+    }
+
+    /// Whether the next attempt is still due. Until then servers that didn't answer at all are
+    /// probed with a plain TCP connection, so that their return is noticed without the full delay.
+    fn backing_off(&mut self) -> bool {
+        if !self.backoff.waiting() {
+            self.probe = None;
+            return false;
+        }
+        // A name that doesn't resolve won't be fixed by the server answering again
+        let unreachable = matches!(
+            self.state.last_error,
+            Some(ConnectionError::TcpTimeout)
+                | Some(ConnectionError::ConnectionRefused)
+                | Some(ConnectionError::Network(_))
+        );
+        if !unreachable {
+            return true;
+        }
+
+        let answered = match &self.probe {
+            Some(probe) => match probe.result.try_recv() {
+                Ok(answered) => answered,
+                Err(TryRecvError::Disconnected) => false,
+                Err(TryRecvError::Empty) => return true,
+            },
+            None => false,
+        };
+        if answered {
+            debug!("[{}] Server answers again, reconnecting", self.hostname);
+            self.probe = None;
+            return false;
+        }
+        if !matches!(&self.probe, Some(probe) if probe.started.elapsed() < PROBE_INTERVAL) {
+            self.probe = Some(Probe::start(&self.hostname, self.port));
+        }
+        true
+    }
+
+    fn exec(&mut self, command: &str) -> Result<String, ConnectionError> {
+        let mut channel = self.channel()?;
+
+        let started = Instant::now();
+        self.timings.sent = Some(SystemTime::now());
+        channel.exec(command).map_err(command_failed)?;
+
+        let mut result = String::new();
+        channel
+            .read_to_string(&mut result)
+            .map_err(command_failed)?;
+        self.timings.command = started.elapsed();
+        Ok(result)
+    }
+
+    /// Connect to server, authenticate and gather information about the system
+    fn init(&mut self) -> Result<(), ConnectionError> {
+        self.session = None;
+        self.sftp = None;
+        info!("[{}] Connecting.", self.hostname);
+        let started = Instant::now();
+        let session = self.try_connect()?;
+        self.timings.connect = started.elapsed();
+
+        self.session = Some(session);
+        self.certificates_modified = self.certificates_modified();
+        info!("[{}] Connection established", self.hostname);
+
+        let system_info = self.run(SYSTEM_INFO_QUERY).unwrap_or_default();
+        self.system_info = SystemInfo::from_string(&system_info);
+        self.cpus = self.system_info.cpus as u8;
+        self.update_uptime();
+        Ok(())
+    }
+
+    fn try_connect(&mut self) -> Result<Session, ConnectionError> {
+        let address = format!("{}:{}", self.hostname, self.port);
+        let mut socket_address = address
+            .to_socket_addrs()
+            .map_err(|e| ConnectionError::Dns(e.to_string()))?;
+        let socket_address = socket_address.next().ok_or_else(|| {
+            ConnectionError::Dns(format!(
+                "Please verify that the address {} is valid",
+                address
+            ))
+        })?;
+
+        debug!("[{}] Opening TCP connection", self.hostname);
+        let timeout = ::std::time::Duration::from_secs(1);
+        let tcp =
+            TcpStream::connect_timeout(&socket_address, timeout).map_err(|e| match e.kind() {
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => ConnectionError::TcpTimeout,
+                ErrorKind::ConnectionRefused => ConnectionError::ConnectionRefused,
+                _ => ConnectionError::Network(e.to_string()),
+            })?;
+        self.ip = tcp
+            .peer_addr()
+            .map_err(|e| ConnectionError::Network(e.to_string()))?
+            .ip()
+            .to_string();
+
+        debug!("[{}] Initializing session", self.hostname);
+        let mut session = Session::new().map_err(handshake_failed)?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(5000);
+
+        debug!("[{}] Performing handshake", self.hostname);
+        session.handshake().map_err(handshake_failed)?;
+        self.verify_host_key(&session)?;
+
+        let mut last_error = ConnectionError::AuthRejected("No method to authenticate".to_string());
+        for auth_method in &self.auth_methods {
+            debug!(
+                "[{}] Authenticating with {}",
+                self.hostname,
+                auth_method.name()
+            );
+            match self.authenticate(&session, auth_method) {
+                Ok(()) if session.authenticated() => return Ok(session),
+                Ok(()) => {
+                    last_error = ConnectionError::AuthRejected(
+                        "Server did not accept the credentials".to_string(),
+                    )
+                }
+                Err(error) => {
+                    warn!(
+                        "[{}] {} authentication failed: {}",
+                        self.hostname,
+                        auth_method.name(),
+                        error
+                    );
+                    last_error = ConnectionError::AuthRejected(error.to_string());
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Refuses servers whose key differs from the one in known_hosts, unknown servers are
+    /// accepted since there was never a way to add them
+    fn verify_host_key(&self, session: &Session) -> Result<(), ConnectionError> {
+        let home = match std::env::var("HOME") {
+            Ok(home) => home,
+            Err(_) => return Ok(()),
+        };
+        let known_hosts_path = Path::new(&home).join(".ssh/known_hosts");
+        let (key, _) = match session.host_key() {
+            Some(host_key) => host_key,
+            None => {
+                return Err(ConnectionError::Handshake(
+                    "No host key received".to_string(),
+                ))
+            }
+        };
+
+        let mut known_hosts = session.known_hosts().map_err(handshake_failed)?;
+        if known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .is_err()
+        {
+            return Ok(());
+        }
+        match known_hosts.check_port(&self.hostname, self.port as u16, key) {
+            CheckResult::Mismatch => Err(ConnectionError::HostKeyMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    fn authenticate(
+        &self,
+        session: &Session,
+        auth_method: &AuthMethod,
+    ) -> Result<(), Box<dyn ::std::error::Error>> {
+        match auth_method {
+            AuthMethod::SshAgent => {
+                self.authenticate_agent(session)?;
+            }
+            AuthMethod::PubKey(config) => {
+                session.userauth_pubkey_file(
+                    &*self.username,
+                    config.public_key_path(),
+                    config.private_key_path(),
+                    config.passphrase(),
+                )?;
+            }
+            AuthMethod::Certificate(config) => {
+                // The certificate takes the place of the public key
+                session.userauth_pubkey_file(
+                    &self.username,
+                    Some(&config.certificate_path()),
+                    config.private_key_path(),
+                    config.passphrase(),
+                )?;
+            }
+            AuthMethod::Password(config) => {
+                session.userauth_password(&self.username, &config.secret()?)?;
+            }
+            AuthMethod::KeyboardInteractive(config) => {
+                let mut prompt = SecretPrompt(config.secret()?);
+                session.userauth_keyboard_interactive(&self.username, &mut prompt)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tries every identity of the agent, certificates first since hardened hosts only accept
+    /// those and give up after a few attempts
+    fn authenticate_agent(&self, session: &Session) -> Result<(), Box<dyn ::std::error::Error>> {
+        let mut agent = session.agent()?;
+        agent.connect()?;
+        agent.list_identities()?;
+        let mut identities = agent.identities()?;
+        identities.sort_by_key(|identity| !is_certificate(identity.blob()));
+
+        let mut last_error: Box<dyn ::std::error::Error> =
+            From::from("No identities found in the SSH agent");
+        for identity in &identities {
+            match agent.userauth(&self.username, identity) {
+                Ok(()) => return Ok(()),
+                Err(error) => {
+                    debug!(
+                        "[{}] Agent identity {} was rejected: {}",
+                        self.hostname,
+                        identity.comment(),
+                        error
+                    );
+                    last_error = error.into();
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn certificates_modified(&self) -> Vec<Option<SystemTime>> {
+        self.auth_methods
+            .iter()
+            .filter_map(|auth_method| match auth_method {
+                AuthMethod::Certificate(config) => Some(config.certificate_path()),
+                _ => None,
+            })
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// Get channel to run command
+    fn channel(&mut self) -> Result<Channel, ConnectionError> {
+        let session = self.session()?;
+
+        let started = Instant::now();
+        let channel = session.channel_session().map_err(command_failed)?;
+        self.timings.channel = started.elapsed();
+        Ok(channel)
+    }
+
+    /// Runs a command on a channel of its own that is handed out to be read
+    fn start(&mut self, command: &str) -> Result<Channel, ConnectionError> {
+        if self.backing_off() {
+            return Err(self.state.waiting_error());
+        }
+
+        let result = self.channel().and_then(|mut channel| {
+            channel.exec(command).map_err(command_failed)?;
+            Ok(channel)
+        });
+        self.backoff
+            .record(&mut self.state, &self.hostname, result.as_ref().map(|_| ()));
+        if result.is_err() {
+            self.session = None;
+        }
+        result
+    }
+
+    /// Reads files through the SFTP subsystem, which is kept open along with the session
+    fn read(&mut self, paths: &[&str]) -> Result<Vec<String>, ConnectionError> {
+        if self.sftp.is_none() {
+            let started = Instant::now();
+            let sftp = match self.session()?.sftp() {
+                Ok(sftp) => sftp,
+                Err(e) => {
+                    warn!(
+                        "[{}] SFTP is not available, reading files with queries: {}",
+                        self.hostname, e
+                    );
+                    self.sftp_unavailable = true;
+                    return Err(command_failed(e));
+                }
+            };
+            self.timings.channel = started.elapsed();
+            self.sftp = Some(sftp);
+        }
+        let sftp = self.sftp.as_ref().ok_or_else(|| {
+            ConnectionError::CommandFailed("SFTP subsystem is not open".to_string())
+        })?;
+
+        let started = Instant::now();
+        let mut contents = Vec::with_capacity(paths.len());
+        for path in paths {
+            let mut content = String::new();
+            match sftp.open(Path::new(path)) {
+                Ok(mut file) => {
+                    file.read_to_string(&mut content).map_err(command_failed)?;
+                }
+                // LIBSSH2_FX_NO_SUCH_FILE, optional files like the conntrack counters
+                Err(e) if e.code() == ErrorCode::SFTP(2) => (),
+                Err(e) => return Err(command_failed(e)),
+            }
+            contents.push(content);
+        }
+        self.timings.command = started.elapsed();
+        Ok(contents)
+    }
+
+    /// Get the session, connecting first if there is none
+    fn session(&mut self) -> Result<&Session, ConnectionError> {
+        // Short-lived certificates are renewed on disk, the session has to be authenticated
+        // again to keep working after the old one expires
+        if self.session.is_some() && self.certificates_modified() != self.certificates_modified {
+            info!("[{}] Certificate was renewed, reconnecting", self.hostname);
+            self.session = None;
+        }
+
+        if self.session.is_none() {
+            self.init()?;
+        }
+        self.session.as_ref().ok_or_else(|| {
+            ConnectionError::CommandFailed("Attempt to connect has failed".to_string())
+        })
+    }
+}
+
+impl Transport for SshClient {
+    /// Run command on server and if it fails invalidate the session and wait before trying again
+    fn run(&mut self, command: &str) -> Result<String, ConnectionError> {
+        if self.backing_off() {
+            return Err(self.state.waiting_error());
+        }
+
+        let result = self.exec(command);
+        self.backoff
+            .record(&mut self.state, &self.hostname, result.as_ref().map(|_| ()));
+        if result.is_err() {
+            self.session = None;
+            self.sftp = None;
+        }
+        result
+    }
+
+    fn can_read_files(&self) -> bool {
+        !self.sftp_unavailable
+    }
+
+    fn read_files(&mut self, paths: &[&str]) -> Result<Vec<String>, ConnectionError> {
+        if self.backing_off() {
+            return Err(self.state.waiting_error());
+        }
+
+        let result = self.read(paths);
+        // A server without SFTP is still reachable, that is no reason to back off
+        if self.sftp_unavailable {
+            return result;
+        }
+        self.backoff
+            .record(&mut self.state, &self.hostname, result.as_ref().map(|_| ()));
+        if result.is_err() {
+            self.session = None;
+            self.sftp = None;
+        }
+        result
+    }
+
+    fn stream(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError> {
+        Ok(Box::new(PolledChannel(self.start(command)?)))
+    }
+
+    fn spawn(&mut self, command: &str) -> Result<Box<dyn Read + Send>, ConnectionError> {
+        Ok(Box::new(PolledChannel(self.start(command)?)))
+    }
+
+    fn update_uptime(&mut self) {
+        let raw_uptime = self
+            .run("cat /proc/uptime")
+            .unwrap_or_else(|_| "0".to_string());
+        let (parts, _): (Vec<&str>, Vec<&str>) = raw_uptime.split(' ').partition(|s| !s.is_empty());
+        let uptime_seconds = parts.get(0).unwrap_or(&"0"); // and_then?
+        let uptime_seconds = f64::from_str(uptime_seconds).unwrap_or(0.0);
+        self.uptime_seconds = uptime_seconds as u64;
+    }
+
+    fn get_hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    fn get_ip(&self) -> &str {
+        &self.ip
+    }
+
+    fn get_cpus(&self) -> u8 {
+        self.cpus
+    }
+
+    fn get_uptime(&self) -> u64 {
+        self.uptime_seconds
+    }
+
+    fn get_system_info(&self) -> &SystemInfo {
+        &self.system_info
+    }
+
+    fn get_timings(&self) -> ExecTimings {
+        self.timings
+    }
+
+    fn reset_timings(&mut self) {
+        self.timings = ExecTimings::default();
+    }
+
+    fn get_state(&self) -> &ConnectionState {
+        &self.state
+    }
+}
+
+fn handshake_failed(error: ssh2::Error) -> ConnectionError {
+    ConnectionError::Handshake(error.to_string())
+}
+
+fn command_failed<E: std::error::Error>(error: E) -> ConnectionError {
+    ConnectionError::CommandFailed(error.to_string())
+}
+
+/// Key blobs start with the length-prefixed key type, e.g. ssh-ed25519-cert-v01@openssh.com
+fn is_certificate(blob: &[u8]) -> bool {
+    let length = match blob.get(..4) {
+        Some(prefix) => u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize,
+        None => return false,
+    };
+    matches!(blob.get(4..4 + length), Some(key_type) if key_type.ends_with(b"-cert-v01@openssh.com"))
+}
+
+/// Answers every keyboard-interactive prompt with the configured secret
+struct SecretPrompt(String);
+
+impl KeyboardInteractivePrompt for SecretPrompt {
+    fn prompt<'a>(&mut self, _: &str, _: &str, prompts: &[Prompt<'a>]) -> Vec<String> {
+        prompts.iter().map(|_| self.0.clone()).collect()
+    }
+}
+
+/// Reads a channel only once data or the end has arrived. Waiting for a silent command would
+/// keep the session locked for up to its timeout and hold up the batches meanwhile.
+struct PolledChannel(Channel);
+
+impl Read for PolledChannel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.0.read_window().available == 0 && !self.0.eof() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.0.read(buf)
+    }
+}
+
+/// Tries a TCP connection to the server on a thread of its own, so that the actor isn't blocked
+struct Probe {
+    result: Receiver<bool>,
+    started: Instant,
+}
+
+impl Probe {
+    fn start(hostname: &str, port: usize) -> Self {
+        let address = format!("{}:{}", hostname, port);
+        let (sender, result) = mpsc::channel();
+        thread::spawn(move || {
+            let answered = match address.to_socket_addrs().ok().and_then(|mut a| a.next()) {
+                Some(socket_address) => {
+                    TcpStream::connect_timeout(&socket_address, PROBE_INTERVAL).is_ok()
+                }
+                None => false,
+            };
+            let _ = sender.send(answered);
+        });
+
+        Self {
+            result,
+            started: Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use super::{ConnectionError, Framing, QueryResult, Transport, POLL_INTERVAL};
+use log::debug;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Ends every sample in the output of the loop, the ASCII record separator
+const SAMPLE_END: u8 = 0x1e;
+
+/// Outputs of the streamed queries from one pass of the loop on the server
+pub struct Sample {
+    pub results: Vec<QueryResult>,
+    pub received: SystemTime,
+}
+
+/// Keeps the queries running in a loop on the server over a single channel instead of starting
+/// them anew every second. Samples are read on a thread of their own.
+pub struct SampleStream {
+    samples: Receiver<Sample>,
+    /// When the stream was started or the last sample arrived
+    last_activity: Instant,
+    ended: bool,
+    /// Tells the reader to give up on a channel that has gone quiet once the stream is dropped
+    stopped: Arc<AtomicBool>,
+}
+
+impl SampleStream {
+    pub fn start(
+        transport: &mut dyn Transport,
+        queries: &[&str],
+        interval: Duration,
+    ) -> Result<Self, ConnectionError> {
+        let framing = Framing::new();
+        let output = transport.stream(&collector_loop(&framing, queries, interval))?;
+        let hostname = transport.get_hostname().to_string();
+        let count = queries.len();
+        let (sender, samples) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let reader_stopped = stopped.clone();
+        thread::spawn(move || {
+            read_samples(output, framing, count, sender, reader_stopped, hostname)
+        });
+
+        Ok(Self {
+            samples,
+            last_activity: Instant::now(),
+            ended: false,
+            stopped,
+        })
+    }
+
+    /// Returns the newest sample that arrived since the last call, older ones are superseded
+    pub fn latest(&mut self) -> Option<Sample> {
+        let mut latest = None;
+        loop {
+            match self.samples.try_recv() {
+                Ok(sample) => latest = Some(sample),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.ended = true;
+                    break;
+                }
+            }
+        }
+        if latest.is_some() {
+            self.last_activity = Instant::now();
+        }
+        latest
+    }
+
+    /// Whether samples are still expected, the loop ends when the server goes away
+    pub fn is_alive(&self, stall_timeout: Duration) -> bool {
+        !self.ended && !self.is_stalled(stall_timeout)
+    }
+
+    /// Whether nothing has arrived for too long, also for streams that ended right away, so
+    /// that a failing command isn't started over on every tick
+    pub fn is_stalled(&self, stall_timeout: Duration) -> bool {
+        self.last_activity.elapsed() > stall_timeout
+    }
+}
+
+impl Drop for SampleStream {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Starts a process for the stream of a command-line transport in place of the previous one
+pub(super) fn spawn_stream(
+    command: &mut Command,
+    running: &mut Option<Child>,
+) -> Result<Box<dyn Read + Send>, ConnectionError> {
+    stop_stream(running);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| ConnectionError::CommandFailed(e.to_string()))?;
+    let output = child.stdout.take().ok_or_else(|| {
+        ConnectionError::CommandFailed("Output of the stream is not available".to_string())
+    })?;
+    *running = Some(child);
+    Ok(Box::new(output))
+}
+
+pub(super) fn stop_stream(running: &mut Option<Child>) {
+    if let Some(mut child) = running.take() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Runs the queries in a loop that ends every pass with the sample separator. Writing to a
+/// closed channel ends the loop once Hearth stops reading.
+fn collector_loop(framing: &Framing, queries: &[&str], interval: Duration) -> String {
+    format!(
+        "while :; do {}; printf '\\036' || exit; sleep {}; done",
+        framing.join(queries),
+        interval.as_secs_f64()
+    )
+}
+
+fn read_samples(
+    output: Box<dyn Read + Send>,
+    framing: Framing,
+    count: usize,
+    sender: Sender<Sample>,
+    stopped: Arc<AtomicBool>,
+    hostname: String,
+) {
+    let mut output = BufReader::new(output);
+    let mut raw_sample = vec![];
+    loop {
+        // What was read before a read would have blocked stays in the sample
+        match output.read_until(SAMPLE_END, &mut raw_sample) {
+            Ok(_) if raw_sample.last() == Some(&SAMPLE_END) => raw_sample.pop(),
+            Ok(_) => break,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                debug!("[{}] Reading samples failed: {}", hostname, e);
+                break;
+            }
+        };
+
+        let sample = Sample {
+            results: framing.split(&String::from_utf8_lossy(&raw_sample), count),
+            received: SystemTime::now(),
+        };
+        raw_sample.clear();
+        // The stream has been replaced when nobody is listening anymore
+        if sender.send(sample).is_err() {
+            break;
+        }
+    }
+    debug!("[{}] Sample stream ended", hostname);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::QueryError;
+    use std::io;
+
+    /// Hands out one part after the other, an empty part stands for a read that would block
+    struct Polled(Vec<&'static str>);
+
+    impl Read for Polled {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            match self.0.remove(0) {
+                "" => Err(ErrorKind::WouldBlock.into()),
+                part => {
+                    buf[..part.len()].copy_from_slice(part.as_bytes());
+                    Ok(part.len())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_samples() {
+        // The second sample arrives in two parts, like from a polled channel
+        let output = Polled(vec![
+            "1\n<<hearth:00ff:0>>2\n<<hearth:00ff:0>>\u{1e}3\n<<hearth:00ff:0>>",
+            "",
+            "<<hearth:00ff:2>>\u{1e}5\n",
+        ]);
+        let (sender, samples) = mpsc::channel();
+        read_samples(
+            Box::new(output),
+            Framing::with_token("00ff"),
+            2,
+            sender,
+            Arc::new(AtomicBool::new(false)),
+            "test".to_string(),
+        );
+
+        let results: Vec<Vec<QueryResult>> = samples.iter().map(|sample| sample.results).collect();
+        assert_eq!(
+            results,
+            vec![
+                vec![Ok("1\n".to_string()), Ok("2\n".to_string())],
+                vec![Ok("3\n".to_string()), Err(QueryError::Failed(2))],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collector_loop() {
+        assert_eq!(
+            collector_loop(
+                &Framing::with_token("00ff"),
+                &["cat /proc/loadavg", "cat /proc/stat"],
+                Duration::from_secs(1)
+            ),
+            "while :; do ( cat /proc/loadavg\n); printf '<<hearth:00ff:%s>>' \"$?\"; \
+             ( cat /proc/stat\n); printf '<<hearth:00ff:%s>>' \"$?\"; \
+             printf '\\036' || exit; sleep 1; done"
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use super::session::SessionMessage;
 use crate::metrics::aggregator::Node;
+use crate::metrics::event::NodeEvent;
 use crate::metrics::hub::MetricHub;
 use crate::ws::session::{Connect, Disconnect};
 use actix::prelude::*;
@@ -23,13 +24,14 @@ pub struct InboundMessage {
 pub enum MessageData {
     NodeMetrics(Vec<Node>),
     ClusterMetrics(Vec<Node>),
+    NodeEvents(Vec<NodeEvent>),
 }
 
 #[derive(Message, Clone, Serialize)]
 #[rtype(result = "()")]
 pub enum Receiver {
-    // At the moment there are no messages addressed to Everyone.
-    // Updates are sent only to subscribers of a particular View
+    // Metric updates are sent only to subscribers of a particular View,
+    // node events are addressed to Everyone
     Everyone,
     SubscribersOf(View),
     Only(usize),